@@ -0,0 +1,20 @@
+//! Decrypt command arguments
+
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct DecryptArgs {
+    /// Encrypted files (as uploaded with --encrypt)
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Output directory (default: next to each input)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Key file used for encryption (instead of the TDLR_PASSPHRASE passphrase or a prompt)
+    #[arg(long, value_name = "FILE")]
+    pub key_file: Option<PathBuf>,
+    /// Overwrite existing output files
+    #[arg(long)]
+    pub force: bool,
+}
@@ -3,12 +3,17 @@
 //! Module structure:
 //! - `root.rs` - Root CLI and Commands enum
 //! - `auth.rs` - Auth command arguments
+//! - `decrypt.rs` - Decrypt command arguments
 //! - `upload.rs` - Upload command arguments
 
 mod auth;
+mod decrypt;
 mod root;
 mod upload;
 
 pub use auth::{AuthCommands, LoginCommands, LoginMethod};
+pub use decrypt::DecryptArgs;
 pub use root::{Cli, Commands};
-pub use upload::UploadArgs;
+pub use upload::{
+    parse_interval, ArchiveFormat, ArchiveScope, CaptionPosition, RmPolicy, SortOrder, UploadArgs,
+};
@@ -1,6 +1,7 @@
 //! Root CLI and Commands enum
 
 use super::auth::AuthCommands;
+use super::decrypt::DecryptArgs;
 use super::upload::UploadArgs;
 use clap::{Parser, Subcommand};
 
@@ -26,5 +27,7 @@ pub enum Commands {
     #[command(subcommand)]
     Auth(AuthCommands),
     /// Upload files/dirs to Telegram
-    Upload(UploadArgs),
+    Upload(Box<UploadArgs>),
+    /// Decrypt files uploaded with --encrypt
+    Decrypt(DecryptArgs),
 }
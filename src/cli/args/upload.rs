@@ -1,12 +1,16 @@
 //! Upload command arguments
 
-use clap::Args;
+use clap::{Args, ValueEnum};
+use std::time::Duration;
 
 #[derive(Args)]
 pub struct UploadArgs {
-    /// Dirs or files to upload
+    /// Dirs or files to upload ('-' reads from stdin)
     #[arg(short, long, required = true, num_args = 1..)]
     pub path: Vec<String>,
+    /// File name for data read from stdin (`-p -`) or for a whole-run archive
+    #[arg(long)]
+    pub name: Option<String>,
     /// Chat ID or username (default: Saved Messages)
     #[arg(short, long, allow_hyphen_values = true)]
     pub chat: Option<String>,
@@ -16,12 +20,54 @@ pub struct UploadArgs {
     /// Exclude specified file extensions (e.g., tmp,log)
     #[arg(short, long, num_args = 1.., value_delimiter = ',')]
     pub exclude: Option<Vec<String>>,
+    /// Only files whose path below the given directory matches a glob (e.g. '**/2024/*.mp4')
+    #[arg(long, value_name = "PATTERN", num_args = 1..)]
+    pub glob: Option<Vec<String>>,
+    /// Only files whose path below the given directory matches a regex
+    #[arg(long, value_name = "REGEX")]
+    pub regex: Option<String>,
+    /// Only files at least this large (e.g. 500K, 10MB, 1.5G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+    /// Only files at most this large
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+    /// Only files modified within this interval (e.g. 7d) or since this date
+    #[arg(long, value_name = "AGE|DATE")]
+    pub newer_than: Option<String>,
+    /// Only files modified before this interval ago (e.g. 30d) or before this date
+    #[arg(long, value_name = "AGE|DATE")]
+    pub older_than: Option<String>,
+    /// Only files for which this expression is true (e.g. 'is_video && size > 100*MB')
+    #[arg(long, value_name = "EXPR")]
+    pub filter: Option<String>,
+    /// Include hidden files and directories (names starting with '.')
+    #[arg(long)]
+    pub hidden: bool,
+    /// Follow symbolic links to directories
+    #[arg(long)]
+    pub follow_symlinks: bool,
+    /// Descend at most N directory levels below each path (1 = only its own files)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_depth: Option<u32>,
     /// Remove files after successful upload
     #[arg(long)]
     pub rm: bool,
+    /// Move files into this directory after successful upload (instead of --rm)
+    #[arg(long, value_name = "DIR", conflicts_with = "rm")]
+    pub move_to: Option<std::path::PathBuf>,
+    /// With several accounts: clean up once any account or all accounts uploaded a file
+    #[arg(long, value_enum, default_value_t = RmPolicy::All)]
+    pub rm_policy: RmPolicy,
     /// Topic ID (must be used with --chat for forum groups)
     #[arg(long, requires = "chat")]
     pub topic: Option<i32>,
+    /// Reply to a message in the destination (message ID or link)
+    #[arg(long, value_name = "MSG_ID|LINK", conflicts_with = "topic")]
+    pub reply_to: Option<String>,
+    /// Post as comments under a channel post (link); sends to its discussion group
+    #[arg(long, value_name = "LINK", conflicts_with_all = ["chat", "to", "topic", "reply_to"])]
+    pub comment_on: Option<String>,
     /// Account user ID(s) to use (default: active account)
     #[arg(short, long, action = clap::ArgAction::Append)]
     pub account: Option<Vec<i64>>,
@@ -34,7 +80,255 @@ pub struct UploadArgs {
     /// Destination peer expression (conflicts with --chat and --topic)
     #[arg(long, conflicts_with_all = ["chat", "topic"])]
     pub to: Option<String>,
-    /// Send files as media group/album (max 10 per group, photos/videos only)
+    /// Send without notification (optionally only where EXPR is true)
+    #[arg(long, value_name = "EXPR", num_args = 0..=1)]
+    pub silent: Option<Option<String>>,
+    /// Hide media behind a spoiler (optionally only where EXPR is true)
+    #[arg(long, value_name = "EXPR", num_args = 0..=1)]
+    pub spoiler: Option<Option<String>>,
+    /// Protect content from forwarding and saving (optionally only where EXPR is true)
+    #[arg(long, value_name = "EXPR", num_args = 0..=1)]
+    pub protect: Option<Option<String>>,
+    /// Send as auto, document, photo, video, audio, voice, animation or video-note (or an expression giving one)
+    #[arg(long = "as", value_name = "KIND|EXPR")]
+    pub send_as: Option<String>,
+    /// Schedule messages for a date (YYYY-MM-DD HH:MM, RFC 3339, Unix time) or a date expression
+    #[arg(long, value_name = "DATETIME|EXPR")]
+    pub schedule: Option<String>,
+    /// Post one file (or album) per interval, e.g. 30m, 2h, 1d, 1h30m (at most 365d)
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval, conflicts_with = "watch")]
+    pub every: Option<Duration>,
+    /// With --every: schedule all posts in Telegram's queue instead of waiting (from --schedule or one interval from now)
+    #[arg(long, requires = "every")]
+    pub queue: bool,
+    /// Order in which files are sent (uploads still run in parallel)
+    #[arg(long, value_enum, default_value_t = SortOrder::Natural)]
+    pub sort: SortOrder,
+    /// Sort key expression for `--sort expr` (numbers compare numerically)
+    #[arg(long, value_name = "EXPR", required_if_eq("sort", "expr"))]
+    pub sort_expr: Option<String>,
+    /// Downscale photos over Telegram's limits (instead of sending them as documents)
+    #[arg(long)]
+    pub resize_photos: bool,
+    /// Send files as albums (max 10 per album; photos/videos and other files in separate albums)
     #[arg(long)]
     pub group: bool,
+    /// Album key expression: files with the same value share albums (e.g. dir)
+    #[arg(long, value_name = "EXPR", requires = "group")]
+    pub group_by: Option<String>,
+    /// Caption expression for each album item (e.g. name)
+    #[arg(long, value_name = "EXPR", requires = "group")]
+    pub item_caption: Option<String>,
+    /// Album caption expression; also has `count` and `files` (instead of --caption)
+    #[arg(
+        long,
+        value_name = "EXPR",
+        requires = "group",
+        conflicts_with = "caption"
+    )]
+    pub album_caption: Option<String>,
+    /// Album item that carries the album caption
+    #[arg(long, value_enum, default_value_t = CaptionPosition::First, requires = "group")]
+    pub album_caption_pos: CaptionPosition,
+    /// Skip files whose content was already uploaded to the same destination
+    #[arg(long)]
+    pub skip_uploaded: bool,
+    /// Keep running and upload new files as they appear in the given directories
+    #[arg(long)]
+    pub watch: bool,
+    /// Seconds a new file must stay unchanged before it is uploaded (with --watch)
+    #[arg(long, default_value_t = 3, requires = "watch")]
+    pub settle: u64,
+    /// Show destination, caption, send mode and size for every file, then exit
+    #[arg(long, conflicts_with = "watch")]
+    pub dry_run: bool,
+    /// Print the dry-run plan as JSON
+    #[arg(long, requires = "dry_run")]
+    pub json: bool,
+    /// Write a per-file report after the run (format from extension: .json or .csv)
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    pub report: Option<std::path::PathBuf>,
+    /// Shell command run after each uploaded (or duplicate) file, with TDLR_* variables
+    #[arg(long, value_name = "CMD", conflicts_with = "dry_run")]
+    pub on_success: Option<String>,
+    /// Shell command run after each failed file, with TDLR_* variables
+    #[arg(long, value_name = "CMD", conflicts_with = "dry_run")]
+    pub on_failure: Option<String>,
+    /// Shell command run at the end, with the JSON summary on stdin
+    #[arg(long, value_name = "CMD", conflicts_with = "dry_run")]
+    pub on_complete: Option<String>,
+    /// Retries per file on FLOOD_WAIT and transient network errors
+    #[arg(long, default_value_t = crate::telegram::upload::DEFAULT_RETRIES)]
+    pub retries: u32,
+    /// Files uploaded in parallel per account
+    #[arg(long, env = "TDLR_CONCURRENT", default_value_t = 10)]
+    pub concurrent: usize,
+    /// Adapt concurrency: lower on flood waits/timeouts, raise while throughput improves
+    #[arg(long, env = "TDLR_ADAPTIVE")]
+    pub adaptive: bool,
+    /// Parts uploaded in parallel within one large file
+    #[arg(long, env = "TDLR_PART_WORKERS", default_value_t = crate::telegram::upload::DEFAULT_PART_WORKERS)]
+    pub part_workers: usize,
+    /// Encrypt files before upload (AES-256-GCM, name gets a .enc suffix)
+    #[arg(long, conflicts_with = "group")]
+    pub encrypt: bool,
+    /// Key file for --encrypt (instead of the TDLR_PASSPHRASE passphrase or a prompt)
+    #[arg(long, value_name = "FILE", requires = "encrypt")]
+    pub key_file: Option<std::path::PathBuf>,
+    /// Pack directories into archives while uploading
+    #[arg(long, value_name = "FORMAT", conflicts_with_all = ["group", "watch"])]
+    pub archive: Option<ArchiveFormat>,
+    /// One archive per directory, or one for the whole run
+    #[arg(long, value_enum, default_value_t = ArchiveScope::Dir, requires = "archive")]
+    pub archive_scope: ArchiveScope,
+    /// Split archives into volumes of this many MB (at most 4000, Telegram's largest file)
+    #[arg(
+        long,
+        value_name = "MB",
+        default_value_t = 2000,
+        value_parser = clap::value_parser!(u64).range(1..=4000),
+        requires = "archive"
+    )]
+    pub volume_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    /// Zstandard-compressed tar
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RmPolicy {
+    /// Uploaded (or already present) with at least one account
+    Any,
+    /// Uploaded (or already present) with every selected account
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// By path, character by character
+    Name,
+    /// By path, with digit runs compared as numbers (ep2 before ep10)
+    Natural,
+    /// Oldest modification time first
+    Mtime,
+    /// Smallest first
+    Size,
+    /// By the value of --sort-expr
+    Expr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CaptionPosition {
+    /// First item of the album
+    First,
+    /// Last item of the album
+    Last,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveScope {
+    /// One archive per directory given in --path
+    Dir,
+    /// A single archive with everything (named by --name)
+    Run,
+}
+
+/// Longest `--every` interval (Telegram schedules at most a year ahead)
+const MAX_INTERVAL_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Parse an interval such as `90s`, `30m`, `2h`, `1d` or `1h30m`
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let too_long = || "interval must be at most 365 days".to_string();
+    let mut secs: u64 = 0;
+    let mut number = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("unknown unit '{}' (use s, m, h or d)", c)),
+        };
+        if number.is_empty() {
+            return Err("expected a number before each unit".to_string());
+        }
+        // Digits that don't fit in u64 are far over the limit
+        let n: u64 = number.parse().map_err(|_| too_long())?;
+        secs = n
+            .checked_mul(unit)
+            .and_then(|s| secs.checked_add(s))
+            .filter(|&s| s <= MAX_INTERVAL_SECS)
+            .ok_or_else(too_long)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err("missing unit (use s, m, h or d)".to_string());
+    }
+    if secs == 0 {
+        return Err("interval must be longer than zero".to_string());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parse a size such as `500K`, `10MB`, `1.5G` or a number of bytes
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    let unit: u64 = match unit
+        .trim()
+        .to_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        "T" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown unit '{}' (use K, M, G or T)", unit)),
+    };
+    Ok((number * unit as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_interval("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_interval("30").is_err());
+        assert!(parse_interval("5x").is_err());
+        assert!(parse_interval("0s").is_err());
+        assert_eq!(parse_interval("365d"), Ok(Duration::from_secs(365 * 86400)));
+        assert!(parse_interval("366d").is_err());
+        assert!(parse_interval("99999999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10MB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1.5g"), Ok(1024 * 1024 * 1536));
+        assert_eq!(parse_size("2KiB"), Ok(2048));
+        assert!(parse_size("10 parsecs").is_err());
+    }
 }
@@ -0,0 +1,62 @@
+//! Decrypt command
+
+use crate::cli::DecryptArgs;
+use crate::crypto::{self, ENCRYPTED_SUFFIX};
+use anyhow::{bail, Result};
+use colored::Colorize;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+pub fn run(args: &DecryptArgs) -> Result<()> {
+    let secret = crypto::load_secret(args.key_file.as_deref(), false)?;
+
+    let mut failed = 0;
+    for input in &args.files {
+        let output = output_path(input, args.output.as_deref());
+        match decrypt_file(input, &output, &secret, args.force) {
+            Ok(()) => println!("{} {} → {}", "✓".green(), input.display(), output.display()),
+            Err(e) => {
+                println!("{} {}: {}", "✗".red(), input.display(), e.to_string().red());
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} file(s) could not be decrypted", failed);
+    }
+    Ok(())
+}
+
+fn decrypt_file(input: &Path, output: &Path, secret: &crypto::Secret, force: bool) -> Result<()> {
+    if output.exists() && !force {
+        bail!("{} exists (use --force to overwrite)", output.display());
+    }
+
+    let reader = BufReader::new(File::open(input)?);
+    let writer = BufWriter::new(File::create(output)?);
+    if let Err(e) = crypto::decrypt(reader, writer, secret) {
+        // Don't leave partial plaintext behind
+        let _ = std::fs::remove_file(output);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Output path: the input name without `.enc`, in `dir` if given
+fn output_path(input: &Path, dir: Option<&Path>) -> PathBuf {
+    let name = input
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match name.strip_suffix(ENCRYPTED_SUFFIX) {
+        Some(stripped) if !stripped.is_empty() => stripped.to_string(),
+        _ => format!("{}.dec", name),
+    };
+
+    match dir {
+        Some(dir) => dir.join(name),
+        None => input.with_file_name(name),
+    }
+}
@@ -1,6 +1,7 @@
 //! Command implementations
 
 mod auth;
+mod decrypt;
 mod hello;
 mod upload;
 mod version;
@@ -14,22 +15,8 @@ pub async fn execute(command: Commands) -> Result<()> {
         Commands::Hello { name } => hello::run(&name),
         Commands::Version => version::run(),
         Commands::Auth(cmd) => execute_auth(cmd).await,
-        Commands::Upload(args) => {
-            upload::run(
-                args.path,
-                args.chat,
-                args.include,
-                args.exclude,
-                args.rm,
-                args.topic,
-                args.account,
-                args.all_accounts,
-                args.caption,
-                args.to,
-                args.group,
-            )
-            .await
-        }
+        Commands::Upload(args) => upload::run(*args).await,
+        Commands::Decrypt(args) => decrypt::run(&args),
     }
 }
 
@@ -0,0 +1,151 @@
+//! Album grouping for `--group` and `--group-by`
+//!
+//! Files sharing a `--group-by` key are sent together, split into albums of
+//! at most 10. Telegram only mixes photos with videos in an album, so
+//! documents and audio form separate albums; voice messages, animations and
+//! video notes are sent one per message. Silent, protect and schedule apply
+//! to a whole album, so files that differ in them never share one.
+//!
+//! Each item may carry its own caption (`--item-caption`); the album caption
+//! (`--caption` or `--album-caption`) goes on the first or last item.
+
+use super::expr::{eval_album_expr, eval_expr, FileContext};
+use super::file::ValidatedFile;
+use super::output;
+use crate::cli::{CaptionPosition, UploadArgs};
+use crate::telegram::upload::{MediaKind, SendOptions, MAX_MEDIA_GROUP_SIZE};
+
+/// Caption settings for albums
+pub struct AlbumCaptions {
+    /// Fixed album caption (`--caption`)
+    caption: Option<String>,
+    /// Album caption expression (`--album-caption`)
+    album: Option<String>,
+    /// Per-item caption expression (`--item-caption`)
+    item: Option<String>,
+    position: CaptionPosition,
+}
+
+impl AlbumCaptions {
+    pub fn new(args: &UploadArgs) -> Self {
+        Self {
+            caption: args.caption.clone(),
+            album: args.album_caption.clone(),
+            item: args.item_caption.clone(),
+            position: args.album_caption_pos,
+        }
+    }
+
+    /// Caption of each item of an album
+    ///
+    /// The album caption comes before the item caption on the item that
+    /// carries it. Expression errors are reported and leave the caption out.
+    pub fn captions(&self, items: &[&FileContext]) -> Vec<Option<String>> {
+        let mut captions: Vec<Option<String>> = match self.item {
+            Some(ref expr) => items
+                .iter()
+                .map(|ctx| caption_or_warn(eval_expr(expr, ctx)))
+                .collect(),
+            None => vec![None; items.len()],
+        };
+
+        let album = match self.album {
+            Some(ref expr) => caption_or_warn(eval_album_expr(expr, items)),
+            None => self.caption.clone(),
+        };
+        let carrier = match self.position {
+            CaptionPosition::First => captions.first_mut(),
+            CaptionPosition::Last => captions.last_mut(),
+        };
+        if let (Some(album), Some(caption)) = (album, carrier) {
+            *caption = Some(match caption.take() {
+                Some(item) => format!("{}\n\n{}", album, item),
+                None => album,
+            });
+        }
+        captions
+    }
+}
+
+/// Non-empty caption from an expression result
+fn caption_or_warn(result: anyhow::Result<String>) -> Option<String> {
+    match result {
+        Ok(caption) => (!caption.trim().is_empty()).then_some(caption),
+        Err(e) => {
+            output::print_warning(&format!("caption expression error: {}", e));
+            None
+        }
+    }
+}
+
+/// Partition of a file in group mode: files only share an album when they
+/// go to the same place with the same album-wide options
+pub fn partition_key(dest: &str, options: &SendOptions, album_key: &str) -> String {
+    format!(
+        "{}\0{:?}\0{}\0{}\0{:?}\0{}",
+        dest, options.reply_to, options.silent, options.protect, options.schedule, album_key
+    )
+}
+
+/// Album key of a file: its `--group-by` value and the album type of its kind
+pub fn album_key(
+    group_by: Option<&str>,
+    file: &ValidatedFile,
+    kind: MediaKind,
+    ctx: &FileContext,
+) -> String {
+    let Some(kind) = kind.resolve(&ctx.name).album() else {
+        // Unique key: never grouped
+        return format!("single\0{}", file.display());
+    };
+    let Some(expr) = group_by else {
+        return kind.to_string();
+    };
+    match eval_expr(expr, ctx) {
+        Ok(key) => format!("{}\0{}", kind, key),
+        Err(e) => {
+            output::print_warning(&format!("group-by expression error: {}", e));
+            kind.to_string()
+        }
+    }
+}
+
+/// Split items into albums: equal keys together, in order of first appearance
+///
+/// Returns the item indices of each album.
+pub fn albums(keys: &[String]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        match groups.iter_mut().find(|(k, _)| *k == key.as_str()) {
+            Some((_, items)) => items.push(i),
+            None => groups.push((key, vec![i])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .flat_map(|(_, items)| {
+            items
+                .chunks(MAX_MEDIA_GROUP_SIZE)
+                .map(<[usize]>::to_vec)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_albums_group_and_split() {
+        let mut keys: Vec<String> = ["a", "b", "a"].iter().map(|k| k.to_string()).collect();
+        keys.extend((0..11).map(|_| "c".to_string()));
+
+        let albums = albums(&keys);
+        assert_eq!(albums[0], vec![0, 2]);
+        assert_eq!(albums[1], vec![1]);
+        assert_eq!(albums[2].len(), MAX_MEDIA_GROUP_SIZE);
+        assert_eq!(albums[3], vec![13]);
+    }
+}
@@ -0,0 +1,407 @@
+//! Packing directories into archives while uploading (`--archive`)
+//!
+//! The archive is written on a blocking thread and streamed through a
+//! channel. Each volume is staged in a temp file while it is uploaded, so a
+//! failed upload can be retried without rebuilding the archive. Archives that may grow
+//! beyond the volume size are split into `name.001`, `name.002`, ... which
+//! can be joined again with `cat`.
+
+use super::file::{collect_files, FileSource, InputFailure, ValidatedFile};
+use super::filter::FileFilter;
+use super::{remote, stdin};
+use crate::cli::{ArchiveFormat, ArchiveScope, UploadArgs};
+use crate::crypto;
+use crate::telegram::upload::UploadSource;
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+
+/// Name of a whole-run archive when `--name` is not given
+const DEFAULT_NAME: &str = "archive";
+
+/// Buffered chunks per volume between the archiver and the upload
+const CHUNK_BUFFER: usize = 16;
+
+/// Size of the chunks handed to the upload
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Archive to build from local files
+pub struct ArchiveSpec {
+    pub format: ArchiveFormat,
+    /// Files on disk and their names inside the archive
+    pub entries: Vec<(PathBuf, String)>,
+    pub volume_size: u64,
+}
+
+impl ArchiveSpec {
+    /// Whether the archive may exceed one volume (decided up front, as names are sent first)
+    pub fn split(&self) -> bool {
+        let estimate: u64 = self
+            .entries
+            .iter()
+            .map(|(path, _)| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0) + 1024)
+            .sum();
+        estimate > self.volume_size
+    }
+}
+
+/// File extension for an archive format
+fn extension(format: ArchiveFormat) -> &'static str {
+    match format {
+        ArchiveFormat::Zip => "zip",
+        ArchiveFormat::Tar => "tar",
+        ArchiveFormat::TarZst => "tar.zst",
+    }
+}
+
+/// Collect the archives (and loose files, in directory scope) to upload
+pub fn collect_archives(
+    args: &UploadArgs,
+    format: ArchiveFormat,
+    filter: &FileFilter,
+) -> (Vec<ValidatedFile>, Vec<InputFailure>) {
+    let mut files = Vec::new();
+    let mut failed = Vec::new();
+    let mut run_entries = Vec::new();
+
+    for path_str in &args.path {
+        if path_str == stdin::STDIN_PATH || remote::is_url(path_str) {
+            continue;
+        }
+
+        let (collected, mut path_failed) = collect_files(std::slice::from_ref(path_str), filter);
+        failed.append(&mut path_failed);
+
+        let path = Path::new(path_str);
+        if !path.is_dir() {
+            match args.archive_scope {
+                ArchiveScope::Dir => files.extend(collected),
+                ArchiveScope::Run => run_entries.extend(collected.into_iter().map(|f| {
+                    let name = f.name();
+                    (f.path, name)
+                })),
+            }
+            continue;
+        }
+
+        let dir_name = dir_name(path);
+        let mut entries: Vec<(PathBuf, String)> = collected
+            .into_iter()
+            .map(|f| {
+                let name = format!("{}/{}", dir_name, entry_name(&f.path, path));
+                (f.path, name)
+            })
+            .collect();
+
+        match args.archive_scope {
+            ArchiveScope::Dir if !entries.is_empty() => {
+                entries.sort_by(|a, b| a.1.cmp(&b.1));
+                let name = format!("{}.{}", dir_name, extension(format));
+                let archive_path = path.parent().unwrap_or(Path::new("")).join(name);
+                files.push(archive_file(archive_path, format, entries, args));
+            }
+            ArchiveScope::Dir => {}
+            ArchiveScope::Run => run_entries.append(&mut entries),
+        }
+    }
+
+    if !run_entries.is_empty() {
+        run_entries.sort_by(|a, b| a.1.cmp(&b.1));
+        let base = args.name.as_deref().unwrap_or(DEFAULT_NAME);
+        let name = format!("{}.{}", base, extension(format));
+        files.push(archive_file(PathBuf::from(name), format, run_entries, args));
+    }
+
+    (files, failed)
+}
+
+fn archive_file(
+    path: PathBuf,
+    format: ArchiveFormat,
+    entries: Vec<(PathBuf, String)>,
+    args: &UploadArgs,
+) -> ValidatedFile {
+    // Encrypted volumes must still fit the volume size
+    let mut volume_size = args.volume_size * 1024 * 1024;
+    if args.encrypt {
+        volume_size = crypto::plain_capacity(volume_size);
+    }
+    ValidatedFile {
+        path,
+        source: FileSource::Archive(Arc::new(ArchiveSpec {
+            format,
+            entries,
+            volume_size,
+        })),
+    }
+}
+
+/// Directory name, resolving `.` and `..`
+fn dir_name(dir: &Path) -> String {
+    dir.canonicalize()
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| DEFAULT_NAME.to_string())
+}
+
+/// Path inside the archive, relative to the archived directory, with `/` separators
+fn entry_name(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+type Chunk = io::Result<Cursor<Vec<u8>>>;
+
+/// Archive volumes produced by a background archiver
+pub struct Volumes {
+    receiver: mpsc::Receiver<mpsc::Receiver<Chunk>>,
+    name: String,
+    split: bool,
+    count: usize,
+}
+
+impl Volumes {
+    /// Start archiving in the background
+    pub fn open(file: &ValidatedFile, spec: Arc<ArchiveSpec>) -> Self {
+        let (sender, receiver) = mpsc::channel(1);
+        let split = spec.split();
+
+        tokio::task::spawn_blocking(move || {
+            let writer = VolumeWriter {
+                volumes: sender,
+                current: None,
+                written: 0,
+                volume_size: spec.volume_size,
+            };
+            let mut out = BufWriter::with_capacity(CHUNK_SIZE, writer);
+            if let Err(e) = write_archive(&spec, &mut out).and_then(|_| out.flush()) {
+                out.get_mut().fail(e);
+            }
+        });
+
+        Self {
+            receiver,
+            name: file.name(),
+            split,
+            count: 0,
+        }
+    }
+
+    /// Whether volumes get numbered names
+    pub fn split(&self) -> bool {
+        self.split
+    }
+
+    /// Number of volumes handed out so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Next volume to upload, or `None` when the archive is complete
+    pub async fn next(&mut self) -> Option<UploadSource> {
+        let mut chunks = self.receiver.recv().await?;
+        self.count += 1;
+
+        let name = if self.split {
+            format!("{}.{:03}", self.name, self.count)
+        } else {
+            self.name.clone()
+        };
+        let stream = futures::stream::poll_fn(move |cx| chunks.poll_recv(cx));
+        Some(UploadSource {
+            reader: Box::new(StreamReader::new(stream)),
+            size: None,
+            name,
+        })
+    }
+}
+
+/// Archive volume copied to a temp file, so a failed upload can be repeated
+///
+/// Only the volume being uploaded is on disk; the file is removed on drop.
+pub struct StagedVolume {
+    path: PathBuf,
+    name: String,
+}
+
+impl StagedVolume {
+    /// Copy a volume out of the archiver
+    pub async fn write(mut source: UploadSource) -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "tdlr-volume-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let mut out = tokio::fs::File::create(&path).await?;
+        // Created first, so a failed copy is cleaned up too
+        let staged = Self {
+            path,
+            name: source.name,
+        };
+        tokio::io::copy(&mut source.reader, &mut out).await?;
+        out.flush().await?;
+        Ok(staged)
+    }
+
+    /// Open the volume for one upload attempt
+    pub async fn open(&self) -> io::Result<UploadSource> {
+        let mut source = UploadSource::open(&self.path).await?;
+        source.name = self.name.clone();
+        Ok(source)
+    }
+}
+
+impl Drop for StagedVolume {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Write the whole archive
+fn write_archive(spec: &ArchiveSpec, out: impl Write) -> io::Result<()> {
+    match spec.format {
+        ArchiveFormat::Tar => {
+            let mut builder = tar::Builder::new(out);
+            append_tar(&mut builder, spec)?;
+            builder.into_inner()?;
+        }
+        ArchiveFormat::TarZst => {
+            let mut builder = tar::Builder::new(zstd::Encoder::new(out, 3)?);
+            append_tar(&mut builder, spec)?;
+            builder.into_inner()?.finish()?;
+        }
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipWriter::new_stream(out);
+            for (path, name) in &spec.entries {
+                let mut file = File::open(path)?;
+                let large = file.metadata()?.len() >= u32::MAX as u64;
+                let options = zip::write::SimpleFileOptions::default().large_file(large);
+                zip.start_file(name.as_str(), options)?;
+                io::copy(&mut file, &mut zip)?;
+            }
+            zip.finish()?;
+        }
+    }
+    Ok(())
+}
+
+fn append_tar<W: Write>(builder: &mut tar::Builder<W>, spec: &ArchiveSpec) -> io::Result<()> {
+    for (path, name) in &spec.entries {
+        builder.append_path_with_name(path, name)?;
+    }
+    Ok(())
+}
+
+/// Sync writer that cuts the archive into volume channels
+struct VolumeWriter {
+    volumes: mpsc::Sender<mpsc::Receiver<Chunk>>,
+    current: Option<mpsc::Sender<Chunk>>,
+    written: u64,
+    volume_size: u64,
+}
+
+impl VolumeWriter {
+    /// Close the current volume (if any) and hand out a new one
+    fn start_volume(&mut self) -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel(CHUNK_BUFFER);
+        self.volumes
+            .blocking_send(receiver)
+            .map_err(|_| upload_stopped())?;
+        self.current = Some(sender);
+        self.written = 0;
+        Ok(())
+    }
+
+    /// Pass an archiving error on to the upload reading the current volume
+    fn fail(&mut self, error: io::Error) {
+        if self.current.is_none() && self.start_volume().is_err() {
+            return;
+        }
+        if let Some(ref sender) = self.current {
+            let _ = sender.blocking_send(Err(error));
+        }
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() || self.written >= self.volume_size {
+            self.start_volume()?;
+        }
+
+        let n = buf.len().min((self.volume_size - self.written) as usize);
+        let sender = self.current.as_ref().ok_or_else(upload_stopped)?;
+        sender
+            .blocking_send(Ok(Cursor::new(buf[..n].to_vec())))
+            .map_err(|_| upload_stopped())?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn upload_stopped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "upload stopped reading the archive",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_volumes_join_to_archive() {
+        let dir = std::env::temp_dir().join(format!("tdlr-archive-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.bin");
+        std::fs::write(&a, vec![1u8; 3000]).unwrap();
+
+        let spec = Arc::new(ArchiveSpec {
+            format: ArchiveFormat::Tar,
+            entries: vec![(a, "photos/a.bin".to_string())],
+            volume_size: 1024,
+        });
+        let file = ValidatedFile {
+            path: PathBuf::from("photos.tar"),
+            source: FileSource::Archive(spec.clone()),
+        };
+
+        let mut volumes = Volumes::open(&file, spec);
+        assert!(volumes.split());
+        let mut joined = Vec::new();
+        while let Some(mut volume) = volumes.next().await {
+            let mut data = Vec::new();
+            volume.reader.read_to_end(&mut data).await.unwrap();
+            assert!(data.len() <= 1024);
+            joined.extend(data);
+        }
+        assert_eq!(volumes.count(), joined.len().div_ceil(1024));
+
+        let mut archive = tar::Archive::new(joined.as_slice());
+        let entry = archive.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_str(), Some("photos/a.bin"));
+        assert_eq!(entry.size(), 3000);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+//! Adaptive concurrency control for file uploads
+//!
+//! In fixed mode the limit stays at `--concurrent`. In adaptive mode the
+//! limit is halved on flood waits and timeouts, and raised by one when the
+//! throughput of the last window improved on the previous one.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Completed uploads per throughput measurement window
+const WINDOW_SIZE: usize = 5;
+
+/// Required throughput gain (in percent) before raising the limit
+const MIN_GAIN_PERCENT: f64 = 5.0;
+
+/// Throughput measurement window
+struct Window {
+    started: Instant,
+    bytes: u64,
+    count: usize,
+    /// Throughput of the previous window in bytes/sec
+    last_rate: Option<f64>,
+}
+
+/// Upload slot limiter
+pub struct Concurrency {
+    semaphore: Semaphore,
+    limit: AtomicUsize,
+    /// Permits to drop instead of returning (pending decreases)
+    debt: AtomicUsize,
+    max: usize,
+    adaptive: bool,
+    window: Mutex<Window>,
+}
+
+/// Held while a file is uploading
+pub struct Slot<'a> {
+    permit: Option<SemaphorePermit<'a>>,
+    owner: &'a Concurrency,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            let paid = self
+                .owner
+                .debt
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |d| d.checked_sub(1))
+                .is_ok();
+            if paid {
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl Concurrency {
+    /// Create a limiter starting at `initial`; adaptive mode may grow up to `max`
+    pub fn new(initial: usize, max: usize, adaptive: bool) -> Self {
+        let initial = initial.max(1);
+        Self {
+            semaphore: Semaphore::new(initial),
+            limit: AtomicUsize::new(initial),
+            debt: AtomicUsize::new(0),
+            max: max.max(initial),
+            adaptive,
+            window: Mutex::new(Window {
+                started: Instant::now(),
+                bytes: 0,
+                count: 0,
+                last_rate: None,
+            }),
+        }
+    }
+
+    /// Upper bound of concurrent uploads (for stream buffering)
+    pub fn max(&self) -> usize {
+        if self.adaptive {
+            self.max
+        } else {
+            self.limit()
+        }
+    }
+
+    /// Current limit
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    /// Wait for a free upload slot
+    pub async fn acquire(&self) -> Slot<'_> {
+        let permit = self.semaphore.acquire().await.ok();
+        Slot {
+            permit,
+            owner: self,
+        }
+    }
+
+    /// Record a finished upload (adaptive mode: may raise the limit)
+    pub fn on_success(&self, bytes: u64) {
+        if !self.adaptive {
+            return;
+        }
+
+        let mut window = self.window.lock().unwrap();
+        window.bytes += bytes;
+        window.count += 1;
+        if window.count < WINDOW_SIZE {
+            return;
+        }
+
+        let elapsed = window.started.elapsed().max(Duration::from_millis(1));
+        let rate = window.bytes as f64 / elapsed.as_secs_f64();
+        let improved = window
+            .last_rate
+            .is_none_or(|last| rate >= last * (1.0 + MIN_GAIN_PERCENT / 100.0));
+
+        window.last_rate = Some(rate);
+        window.started = Instant::now();
+        window.bytes = 0;
+        window.count = 0;
+        drop(window);
+
+        if improved {
+            self.increase();
+        }
+    }
+
+    /// Record a flood wait or timeout (adaptive mode: halves the limit)
+    pub fn on_throttle(&self) {
+        if !self.adaptive {
+            return;
+        }
+
+        let current = self.limit();
+        let target = (current / 2).max(1);
+        if target == current {
+            return;
+        }
+        self.limit.store(target, Ordering::SeqCst);
+
+        // Take free permits now, the rest when running uploads finish
+        let mut remove = current - target;
+        while remove > 0 {
+            match self.semaphore.try_acquire() {
+                Ok(permit) => permit.forget(),
+                Err(_) => break,
+            }
+            remove -= 1;
+        }
+        self.debt.fetch_add(remove, Ordering::SeqCst);
+
+        // Throughput before the throttle is no longer a fair baseline
+        self.window.lock().unwrap().last_rate = None;
+    }
+
+    /// Raise the limit by one
+    fn increase(&self) {
+        let current = self.limit();
+        if current >= self.max {
+            return;
+        }
+        self.limit.store(current + 1, Ordering::SeqCst);
+
+        let cancelled = self
+            .debt
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |d| d.checked_sub(1))
+            .is_ok();
+        if !cancelled {
+            self.semaphore.add_permits(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_mode_ignores_signals() {
+        let c = Concurrency::new(4, 8, false);
+        c.on_throttle();
+        assert_eq!(c.limit(), 4);
+        assert_eq!(c.max(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_throttle_halves_and_releases() {
+        let c = Concurrency::new(4, 8, true);
+        let a = c.acquire().await;
+        let b = c.acquire().await;
+        let _held = c.acquire().await;
+        c.on_throttle();
+        assert_eq!(c.limit(), 2);
+        // Only one permit was free, so the second removal is deferred
+        assert_eq!(c.semaphore.available_permits(), 0);
+        drop(a);
+        assert_eq!(c.semaphore.available_permits(), 0);
+        drop(b);
+        assert_eq!(c.semaphore.available_permits(), 1);
+    }
+
+    #[test]
+    fn test_increase_up_to_max() {
+        let c = Concurrency::new(2, 3, true);
+        c.increase();
+        c.increase();
+        assert_eq!(c.limit(), 3);
+        assert_eq!(c.semaphore.available_permits(), 3);
+    }
+}
@@ -45,6 +45,10 @@
 //! - `total` - Total number of files (int)
 //! - `num` - Current file number (1-based) (int)
 //!
+//! ## Album Context (`--album-caption`, from the album's first file otherwise)
+//! - `count` - Number of files in the album (int)
+//! - `files` - File names in the album, one per line (string)
+//!
 //! ## Constants
 //! - `KB`, `MB`, `GB` - Size constants for comparison
 //!
@@ -87,6 +91,8 @@
 //! - Comparison: `==`, `!=`, `<`, `>`, `<=`, `>=`
 //! - Logic: `&&`, `||`, `!`
 
+use super::file::ValidatedFile;
+use super::output;
 use anyhow::{anyhow, Result};
 use evalexpr::*;
 use std::path::Path;
@@ -157,6 +163,13 @@ impl FileContext {
         }
     }
 
+    /// Context for a file to upload (size is 0 when not known in advance)
+    pub fn from_file(file: &ValidatedFile, index: usize, total: usize) -> Self {
+        let mut ctx = Self::from_path_with_context(&file.path, index, total);
+        ctx.size = file.size().unwrap_or(0);
+        ctx
+    }
+
     /// Build evalexpr context with all variables
     pub fn to_eval_context(&self) -> HashMapContext {
         let now = chrono::Local::now();
@@ -270,12 +283,26 @@ pub fn eval_expr(expr: &str, ctx: &FileContext) -> Result<String> {
     }
 }
 
+/// Evaluate an album caption expression (adds `count` and `files`)
+pub fn eval_album_expr(expr: &str, items: &[&FileContext]) -> Result<String> {
+    let first = items.first().ok_or_else(|| anyhow!("Empty album"))?;
+    let mut eval_ctx = first.to_eval_context();
+    let names: Vec<&str> = items.iter().map(|c| c.name.as_str()).collect();
+    let _ = eval_ctx.set_value("count".into(), Value::Int(items.len() as i64));
+    let _ = eval_ctx.set_value("files".into(), Value::String(names.join("\n")));
+
+    match eval_with_context(expr, &eval_ctx) {
+        Ok(value) => Ok(value_to_string(&value)),
+        Err(e) => Err(anyhow!("Expression error: {}", e)),
+    }
+}
+
 /// Evaluate a routing expression (returns destination string)
 pub fn eval_routing(expr: &str, ctx: &FileContext) -> String {
     match eval_expr(expr, ctx) {
         Ok(result) => result,
         Err(e) => {
-            eprintln!("Warning: routing expression error: {}", e);
+            output::print_warning(&format!("routing expression error: {}", e));
             "me".to_string() // Default to Saved Messages on error
         }
     }
@@ -455,4 +482,13 @@ mod tests {
         let result = eval_expr("if(size > 50 * MB, \"large\", \"small\")", &ctx).unwrap();
         assert_eq!(result, "large");
     }
+
+    #[test]
+    fn test_album_expr() {
+        let first = test_ctx();
+        let mut second = test_ctx();
+        second.name = "photo.jpg".to_string();
+        let result = eval_album_expr(r#"str::from(count) + ": " + files"#, &[&first, &second]);
+        assert_eq!(result.unwrap(), "2: video.mp4\nphoto.jpg");
+    }
 }
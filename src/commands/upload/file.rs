@@ -1,116 +1,359 @@
 //! File processing utilities for upload
+//!
+//! Directories are walked recursively, skipping hidden entries (unless
+//! `--hidden`), anything matched by `.tdlrignore` / `.gitignore` files and
+//! special files such as sockets and FIFOs. Symlinked directories are only
+//! entered with `--follow-symlinks`.
 
+use super::archive::ArchiveSpec;
+use super::filter::FileFilter;
+use super::{remote, stdin};
+use crate::telegram::upload::UploadSource;
+use anyhow::{bail, Result};
 use colored::Colorize;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Validated file ready for upload
 pub struct ValidatedFile {
+    /// Local path, or the display name for non-file sources
     pub path: PathBuf,
+    pub source: FileSource,
 }
 
-/// File extension filter
-pub struct FileFilter {
-    include: Option<Vec<String>>,
-    exclude: Option<Vec<String>>,
+/// Where the file's data comes from
+#[derive(Clone)]
+pub enum FileSource {
+    Local,
+    Stdin,
+    /// HTTP(S) URL, with the size reported by the server
+    Url {
+        url: String,
+        size: Option<u64>,
+    },
+    /// Archive packed while uploading (see `archive.rs`)
+    Archive(Arc<ArchiveSpec>),
+    /// Stdin or a URL copied into a temp file (see `spool.rs`)
+    Spooled {
+        data: PathBuf,
+        /// URL the data came from (`None` for stdin)
+        url: Option<String>,
+    },
 }
 
-impl FileFilter {
-    pub fn new(include: Option<Vec<String>>, exclude: Option<Vec<String>>) -> Self {
-        // Normalize extensions (remove leading dots, lowercase)
-        let include = include.map(|v| v.iter().map(|s| normalize_ext(s)).collect());
-        let exclude = exclude.map(|v| v.iter().map(|s| normalize_ext(s)).collect());
-        Self { include, exclude }
-    }
-
-    /// Check if a file passes the filter
-    pub fn matches(&self, path: &Path) -> bool {
-        let ext = path
-            .extension()
-            .and_then(|e| e.to_str())
-            .map(|s| s.to_lowercase())
-            .unwrap_or_default();
-
-        // If include is set, file must match one of the extensions
-        if let Some(ref includes) = self.include {
-            if !includes.contains(&ext) {
-                return false;
-            }
+impl ValidatedFile {
+    /// A file on the local filesystem
+    pub fn local(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            source: FileSource::Local,
         }
+    }
 
-        // If exclude is set, file must not match any of the extensions
-        if let Some(ref excludes) = self.exclude {
-            if excludes.contains(&ext) {
-                return false;
-            }
+    /// Whether the data is a file on the local filesystem
+    pub fn is_local(&self) -> bool {
+        matches!(self.source, FileSource::Local)
+    }
+
+    /// Local file holding the data: the file itself, or a spooled copy
+    pub fn data_path(&self) -> Option<&Path> {
+        match self.source {
+            FileSource::Local => Some(&self.path),
+            FileSource::Spooled { ref data, .. } => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Path shown in reports and hooks (the URL for downloaded files)
+    pub fn display(&self) -> String {
+        match self.source {
+            FileSource::Url { ref url, .. } => url.clone(),
+            FileSource::Spooled {
+                url: Some(ref url), ..
+            } => url.clone(),
+            _ => self.path.display().to_string(),
         }
+    }
+
+    /// Size in bytes, if known before reading
+    pub fn size(&self) -> Option<u64> {
+        match self.source {
+            FileSource::Local => fs::metadata(&self.path).map(|m| m.len()).ok(),
+            FileSource::Stdin => None,
+            FileSource::Url { size, .. } => size,
+            FileSource::Archive(_) => None,
+            FileSource::Spooled { ref data, .. } => fs::metadata(data).map(|m| m.len()).ok(),
+        }
+    }
 
-        true
+    /// File name sent to Telegram
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Open the data for upload
+    pub async fn open(&self) -> Result<UploadSource> {
+        match self.source {
+            FileSource::Local => Ok(UploadSource::open(&self.path).await?),
+            FileSource::Stdin => stdin::open(self.name()),
+            FileSource::Url { ref url, .. } => remote::open(url, self.name()).await,
+            FileSource::Archive(_) => bail!("Archives are uploaded volume by volume"),
+            FileSource::Spooled { ref data, .. } => {
+                let mut source = UploadSource::open(data).await?;
+                source.name = self.name();
+                Ok(source)
+            }
+        }
     }
 }
 
-/// Normalize extension string (remove leading dot, lowercase)
-fn normalize_ext(s: &str) -> String {
-    s.trim_start_matches('.').to_lowercase()
+/// An input path that could not be read
+pub struct InputFailure {
+    pub path: String,
+    pub error: String,
 }
 
+/// Ignore files read in every directory, in order of precedence
+pub const IGNORE_FILES: [&str; 2] = [".tdlrignore", ".gitignore"];
+
 /// Collect all files from paths (supports both files and directories)
-pub fn collect_files(paths: &[String], filter: &FileFilter) -> (Vec<ValidatedFile>, usize) {
+pub fn collect_files(
+    paths: &[String],
+    filter: &FileFilter,
+) -> (Vec<ValidatedFile>, Vec<InputFailure>) {
     let mut files = Vec::new();
-    let mut failed = 0;
+    let mut failed = Vec::new();
 
     for path_str in paths {
+        // Stdin and URLs are added separately (see `stdin.rs`, `remote.rs`)
+        if path_str == stdin::STDIN_PATH || remote::is_url(path_str) {
+            continue;
+        }
+
         let path = Path::new(path_str);
 
         if !path.exists() {
             println!("{} Path not found: {}", "✗".red(), path_str.red());
-            failed += 1;
+            failed.push(InputFailure {
+                path: path_str.clone(),
+                error: "Path not found".to_string(),
+            });
             continue;
         }
 
         if path.is_file() {
-            if filter.matches(path) {
-                files.push(ValidatedFile {
-                    path: path.to_path_buf(),
-                });
+            let name = path.file_name().map(Path::new).unwrap_or(path);
+            if filter.matches(path, name) {
+                files.push(ValidatedFile::local(path));
             }
         } else if path.is_dir() {
-            let (dir_files, dir_failed) = collect_from_dir(path, filter);
-            files.extend(dir_files);
-            failed += dir_failed;
+            let mut walk = Walk::new(filter, path);
+            walk.dir(path, 0);
+            files.extend(walk.files);
+            failed.append(&mut walk.failed);
         }
     }
 
     (files, failed)
 }
 
-/// Recursively collect files from a directory
-fn collect_from_dir(dir: &Path, filter: &FileFilter) -> (Vec<ValidatedFile>, usize) {
-    let mut files = Vec::new();
-    let mut failed = 0;
+/// Collect the files at `path` inside the directory `root`, as a walk of
+/// `root` would (used for paths reported by `--watch`)
+pub fn collect_under(root: &Path, path: &Path, filter: &FileFilter) -> Vec<ValidatedFile> {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Vec::new();
+    };
+    let components: Vec<_> = relative.components().collect();
+    let Some(depth) = components.len().checked_sub(1) else {
+        return Vec::new();
+    };
 
-    let entries = match fs::read_dir(dir) {
-        Ok(e) => e,
-        Err(e) => {
-            println!("{} Cannot read dir {}: {}", "✗".red(), dir.display(), e);
-            return (files, 1);
+    // Load the ignore files of every directory on the way down
+    let mut walk = Walk::new(filter, root);
+    walk.enter(root);
+    let mut dir = root.to_path_buf();
+    for component in &components[..depth] {
+        dir.push(component);
+        let within_depth = filter.max_depth.is_none_or(|max| walk.ignores.len() < max);
+        if !within_depth || walk.skip(&dir, true) {
+            return Vec::new();
         }
-    };
+        walk.enter(&dir);
+    }
+    walk.entry(path.to_path_buf(), depth);
+    walk.files
+}
 
-    for entry in entries.flatten() {
-        let path = entry.path();
-        if path.is_file() {
-            if filter.matches(&path) {
-                files.push(ValidatedFile {
-                    path: path.to_path_buf(),
+/// A recursive directory walk
+struct Walk<'a> {
+    filter: &'a FileFilter,
+    /// Directory given in `--path`
+    root: &'a Path,
+    /// Ignore rules of the directories being walked, outermost first
+    ignores: Vec<Gitignore>,
+    /// Canonical paths of the directories being walked, to detect symlink loops
+    ancestors: Vec<PathBuf>,
+    files: Vec<ValidatedFile>,
+    failed: Vec<InputFailure>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(filter: &'a FileFilter, root: &'a Path) -> Self {
+        Self {
+            filter,
+            root,
+            ignores: Vec::new(),
+            ancestors: Vec::new(),
+            files: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    /// Collect the files of a directory `depth` levels below the walked path
+    fn dir(&mut self, dir: &Path, depth: usize) {
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) => {
+                println!("{} Cannot read dir {}: {}", "✗".red(), dir.display(), e);
+                self.failed.push(InputFailure {
+                    path: dir.display().to_string(),
+                    error: format!("Cannot read dir: {}", e),
                 });
+                return;
+            }
+        };
+
+        if let Ok(canonical) = dir.canonicalize() {
+            if self.ancestors.contains(&canonical) {
+                println!("{} Skipping symlink loop: {}", "⚠".yellow(), dir.display());
+                return;
+            }
+            self.ancestors.push(canonical);
+        }
+        self.enter(dir);
+
+        for entry in entries.flatten() {
+            self.entry(entry.path(), depth);
+        }
+
+        self.ignores.pop();
+        self.ancestors.pop();
+    }
+
+    /// Collect a file, or the files of a directory, inside a walked directory
+    fn entry(&mut self, path: PathBuf, depth: usize) {
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            return;
+        };
+        let is_link = meta.file_type().is_symlink();
+        // Links to files are uploaded as the file; links to directories need --follow-symlinks
+        let meta = if is_link {
+            match fs::metadata(&path) {
+                Ok(m) => m,
+                Err(_) => return,
+            }
+        } else {
+            meta
+        };
+
+        if meta.is_dir() {
+            let within_depth = self.filter.max_depth.is_none_or(|max| depth + 1 < max);
+            if within_depth && (!is_link || self.filter.follow_symlinks) && !self.skip(&path, true)
+            {
+                self.dir(&path, depth + 1);
+            }
+        } else if meta.is_file() && !self.skip(&path, false) {
+            let relative = path.strip_prefix(self.root).unwrap_or(&path);
+            if self.filter.matches(&path, relative) {
+                self.files.push(ValidatedFile::local(path));
             }
-        } else if path.is_dir() {
-            let (sub_files, sub_failed) = collect_from_dir(&path, filter);
-            files.extend(sub_files);
-            failed += sub_failed;
         }
+        // Anything else (sockets, FIFOs, devices) is skipped
     }
 
-    (files, failed)
+    /// Read the ignore files of a directory about to be walked
+    fn enter(&mut self, dir: &Path) {
+        let mut builder = GitignoreBuilder::new(dir);
+        // Later files take precedence, so the preferred one goes last
+        for name in IGNORE_FILES.iter().rev() {
+            let path = dir.join(name);
+            if path.is_file() {
+                if let Some(e) = builder.add(&path) {
+                    println!("{} {}: {}", "⚠".yellow(), path.display(), e);
+                }
+            }
+        }
+        self.ignores
+            .push(builder.build().unwrap_or_else(|_| Gitignore::empty()));
+    }
+
+    /// Whether an entry is hidden or ignored
+    fn skip(&self, path: &Path, is_dir: bool) -> bool {
+        let hidden = path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+        if hidden && !self.filter.hidden {
+            return true;
+        }
+        // The innermost ignore file with a matching pattern decides
+        self.ignores
+            .iter()
+            .rev()
+            .map(|ignore| ignore.matched(path, is_dir))
+            .find(|m| !m.is_none())
+            .is_some_and(|m| m.is_ignore())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk_skips_hidden_and_ignored() {
+        let dir = std::env::temp_dir().join(format!("tdlr-walk-{}", std::process::id()));
+        for path in ["a.txt", ".b.txt", "c.log", "keep/d.log", "skip/e.txt"] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "*.log\nskip/\n").unwrap();
+        fs::write(dir.join("keep/.tdlrignore"), "!*.log\n").unwrap();
+
+        let filter = FileFilter::default();
+        let mut walk = Walk::new(&filter, &dir);
+        walk.dir(&dir, 0);
+        let mut names: Vec<_> = walk.files.iter().map(|f| f.name()).collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "d.log"]);
+        assert_eq!(
+            collect_under(&dir, &dir.join("skip/e.txt"), &filter).len(),
+            0
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spooled_url_keeps_identity() {
+        let file = ValidatedFile {
+            path: PathBuf::from("video.mp4"),
+            source: FileSource::Spooled {
+                data: PathBuf::from("/tmp/tdlr-spool-1/0/video.mp4"),
+                url: Some("https://example.com/video.mp4".to_string()),
+            },
+        };
+        assert!(!file.is_local());
+        assert_eq!(file.name(), "video.mp4");
+        assert_eq!(file.display(), "https://example.com/video.mp4");
+        assert_eq!(
+            file.data_path(),
+            Some(Path::new("/tmp/tdlr-spool-1/0/video.mp4"))
+        );
+    }
 }
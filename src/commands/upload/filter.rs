@@ -0,0 +1,221 @@
+//! File filters (`--include`, `--glob`, `--regex`, `--min-size`, `--newer-than`, `--filter`, ...)
+//!
+//! Globs and the regex match the path below the directory given in `--path`
+//! with `/` separators (the file name for files given directly). Ages such as
+//! `7d` count back from now; dates are local (`2024-01-31`, `2024-01-31 18:00`).
+
+use super::expr::FileContext;
+use super::file::IGNORE_FILES;
+use super::output;
+use super::send::{is_sidecar, parse_date};
+use crate::cli::{parse_interval, UploadArgs};
+use anyhow::{anyhow, Result};
+use evalexpr::{build_operator_tree, Node};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which files to collect
+#[derive(Default)]
+pub struct FileFilter {
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    globs: Option<GlobSet>,
+    regex: Option<Regex>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    expr: Option<Node>,
+    /// Walk hidden files and directories
+    pub hidden: bool,
+    /// Enter symlinked directories
+    pub follow_symlinks: bool,
+    /// Directory levels to walk below each path
+    pub max_depth: Option<usize>,
+}
+
+impl FileFilter {
+    pub fn new(args: &UploadArgs) -> Result<Self> {
+        // Normalize extensions (remove leading dots, lowercase)
+        let normalize = |v: &Vec<String>| v.iter().map(|s| normalize_ext(s)).collect();
+        Ok(Self {
+            include: args.include.as_ref().map(normalize),
+            exclude: args.exclude.as_ref().map(normalize),
+            globs: args.glob.as_deref().map(build_globs).transpose()?,
+            regex: args
+                .regex
+                .as_deref()
+                .map(|r| Regex::new(r).map_err(|e| anyhow!("Invalid --regex: {}", e)))
+                .transpose()?,
+            min_size: args.min_size,
+            max_size: args.max_size,
+            newer_than: args
+                .newer_than
+                .as_deref()
+                .map(|v| parse_time("--newer-than", v))
+                .transpose()?,
+            older_than: args
+                .older_than
+                .as_deref()
+                .map(|v| parse_time("--older-than", v))
+                .transpose()?,
+            expr: args
+                .filter
+                .as_deref()
+                .map(|e| build_operator_tree(e).map_err(|e| anyhow!("Invalid --filter: {}", e)))
+                .transpose()?,
+            hidden: args.hidden,
+            follow_symlinks: args.follow_symlinks,
+            max_depth: args.max_depth.map(|d| d as usize),
+        })
+    }
+
+    /// Check if a file passes the filter; `relative` is its path below the walked directory
+    pub fn matches(&self, path: &Path, relative: &Path) -> bool {
+        // Sidecars and ignore files hold options for other files
+        if is_sidecar(path) || path.file_name().is_some_and(|n| n == IGNORE_FILES[0]) {
+            return false;
+        }
+
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|s| s.to_lowercase())
+            .unwrap_or_default();
+
+        // If include is set, file must match one of the extensions
+        if let Some(ref includes) = self.include {
+            if !includes.contains(&ext) {
+                return false;
+            }
+        }
+
+        // If exclude is set, file must not match any of the extensions
+        if let Some(ref excludes) = self.exclude {
+            if excludes.contains(&ext) {
+                return false;
+            }
+        }
+
+        if let Some(ref globs) = self.globs {
+            if !globs.is_match(relative) {
+                return false;
+            }
+        }
+
+        if let Some(ref regex) = self.regex {
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            if !regex.is_match(&relative) {
+                return false;
+            }
+        }
+
+        self.matches_metadata(path) && self.matches_expr(path)
+    }
+
+    /// Size and modification time bounds
+    fn matches_metadata(&self, path: &Path) -> bool {
+        let bounded = self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+            || self.older_than.is_some();
+        if !bounded {
+            return true;
+        }
+        let Ok(meta) = fs::metadata(path) else {
+            return false;
+        };
+
+        let size = meta.len();
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let Ok(mtime) = meta.modified() else {
+                return false;
+            };
+            if self.newer_than.is_some_and(|t| mtime < t)
+                || self.older_than.is_some_and(|t| mtime >= t)
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The `--filter` expression (errors exclude the file)
+    fn matches_expr(&self, path: &Path) -> bool {
+        let Some(ref expr) = self.expr else {
+            return true;
+        };
+        let ctx = FileContext::from_path_with_context(path, 0, 0);
+        match expr.eval_boolean_with_context(&ctx.to_eval_context()) {
+            Ok(keep) => keep,
+            Err(e) => {
+                output::print_warning(&format!(
+                    "filter expression error for {}: {}",
+                    path.display(),
+                    e
+                ));
+                false
+            }
+        }
+    }
+}
+
+/// Normalize extension string (remove leading dot, lowercase)
+fn normalize_ext(s: &str) -> String {
+    s.trim_start_matches('.').to_lowercase()
+}
+
+/// Build `--glob` patterns; `*` stays within a directory, `**` crosses them
+fn build_globs(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| anyhow!("Invalid --glob '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// Parse an age (`7d`, counted back from now) or a date
+fn parse_time(flag: &str, value: &str) -> Result<SystemTime> {
+    if let Ok(age) = parse_interval(value) {
+        return Ok(SystemTime::now() - age);
+    }
+    let time = parse_date(value).map_err(|_| {
+        anyhow!(
+            "Invalid {} '{}' (expected an age like 7d or a date)",
+            flag,
+            value
+        )
+    })?;
+    Ok(UNIX_EPOCH + Duration::from_secs(time.max(0) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_and_regex() {
+        let filter = FileFilter {
+            globs: Some(build_globs(&["**/2024/*.mp4".to_string()]).unwrap()),
+            regex: Some(Regex::new(r"^trips/").unwrap()),
+            ..Default::default()
+        };
+        let path = Path::new("/media/trips/2024/a.mp4");
+        assert!(filter.matches(path, Path::new("trips/2024/a.mp4")));
+        assert!(!filter.matches(path, Path::new("trips/2024/raw/a.mp4")));
+        assert!(!filter.matches(path, Path::new("work/2024/a.mp4")));
+    }
+}
@@ -1,219 +1,1277 @@
 //! Upload handlers for single files and media groups
 
+use super::album::{album_key, albums, partition_key, AlbumCaptions};
+use super::archive::{ArchiveSpec, StagedVolume, Volumes};
+use super::concurrency::Concurrency;
 use super::expr::{eval_routing, FileContext};
-use super::file::ValidatedFile;
+use super::file::{FileSource, ValidatedFile};
+use super::hooks::Hooks;
+use super::index::{hash_file, Claim, Sent, UploadIndex};
+use super::order::SendOrder;
 use super::output;
+use super::photo::{check_photo, convert_photo, Convert, PhotoCheck};
+use super::progress::UploadProgress;
+use super::report::{FileRecord, FileStatus};
+use super::send::SendRules;
+use super::video::is_animation;
+use crate::cli::RmPolicy;
+use crate::crypto::{encrypted_size, EncryptReader, MasterKey, ENCRYPTED_SUFFIX};
 use crate::telegram::upload::{
-    is_media_group_supported, resolve_chat, upload_file, upload_media_group, ResolvedChat,
-    MAX_MEDIA_GROUP_SIZE,
+    classify, is_photo_rejected, photo_as_document, prepare_album_item, resolve_chat, send_album,
+    send_media, upload_media, with_retry, AlbumMedia, Failure, FloodGate, MediaKind, Progress,
+    ResolvedChat, RetryPolicy, SendOptions, UploadSource,
 };
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use futures::stream::{self, StreamExt};
+use futures::FutureExt;
 use grammers_client::Client;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use grammers_tl_types::enums::InputMedia;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Files in flight per upload slot, so uploads go on while an earlier file
+/// is still waiting to be sent
+const UPLOAD_AHEAD: usize = 4;
 
 /// Upload result statistics
 #[derive(Default)]
 pub struct UploadStats {
     pub success: usize,
     pub failed: usize,
+    pub skipped: usize,
+    /// Per-file results (for `--report`)
+    pub records: Vec<FileRecord>,
+    /// Per-file hooks, started as each result comes in
+    pub hooks: Hooks,
 }
 
 impl UploadStats {
-    pub fn add_success(&mut self, count: usize) {
-        self.success += count;
+    pub fn new(hooks: Hooks) -> Self {
+        Self {
+            hooks,
+            ..Default::default()
+        }
     }
 
-    pub fn add_failed(&mut self, count: usize) {
-        self.failed += count;
+    /// Add a per-file result and update the counters
+    pub fn add_record(&mut self, record: FileRecord) {
+        match record.status {
+            FileStatus::Uploaded | FileStatus::Scheduled => self.success += 1,
+            FileStatus::Failed => self.failed += 1,
+            FileStatus::Skipped => self.skipped += 1,
+        }
+        self.hooks.file(&record);
+        self.records.push(record);
     }
 }
 
 /// Upload context for a single upload operation
 pub struct UploadContext<'a> {
     pub client: &'a Client,
+    pub user_id: i64,
     pub chat: &'a Option<String>,
-    pub topic: Option<i32>,
+    /// Silent, spoiler, protect, schedule and reply target per file
+    pub send: &'a SendRules,
     pub caption: &'a Option<String>,
+    /// Album and per-item captions in group mode
+    pub captions: &'a AlbumCaptions,
     pub to: &'a Option<String>,
-    pub concurrent: usize,
+    /// Discussion group of `--comment-on`, used for every file
+    pub discussion: Option<&'a ResolvedChat>,
+    /// Copy of the `--comment-on` post in the discussion group, replied to by every file
+    pub thread: Option<i32>,
+    /// Peer ID of the chat of a `--reply-to` link; other destinations are rejected
+    pub reply_chat: Option<i64>,
+    /// Album key expression for `--group-by`
+    pub group_by: &'a Option<String>,
+    pub concurrency: &'a Concurrency,
+    pub part_workers: usize,
+    /// Index successful uploads are recorded in
+    pub index: Option<&'a UploadIndex>,
+    /// Skip files the index has seen sent to the destination (`--skip-uploaded`)
+    pub skip_uploaded: bool,
+    pub retry: RetryPolicy,
+    pub gate: &'a FloodGate,
+    pub progress: &'a UploadProgress,
+    /// Encryption key for `--encrypt`
+    pub encryption: Option<&'a MasterKey>,
+    /// Downscale photos over Telegram's limits instead of sending them as documents
+    pub resize_photos: bool,
 }
 
-/// Handle single file uploads with concurrency
-pub async fn upload_single_files(
-    ctx: &UploadContext<'_>,
-    files: &[ValidatedFile],
-    stats: &mut UploadStats,
-) -> Result<()> {
-    let total = files.len();
+impl UploadContext<'_> {
+    /// Open a file for upload, converting photos and encrypting if requested
+    ///
+    /// Photos are converted here, under the caller's upload slot, so only the
+    /// photos being uploaded are held in memory.
+    async fn open(&self, file: &ValidatedFile, convert: Option<Convert>) -> Result<UploadSource> {
+        let source = match convert {
+            Some(convert) => {
+                let path = file.data_path().unwrap_or(&file.path).to_path_buf();
+                let jpeg = tokio::task::spawn_blocking(move || convert_photo(&path, convert))
+                    .await
+                    .unwrap_or_else(|e| Err(e.into()));
+                match jpeg {
+                    Ok(jpeg) => UploadSource {
+                        size: Some(jpeg.len() as u64),
+                        reader: Box::new(Cursor::new(jpeg)),
+                        name: format!(
+                            "{}.jpg",
+                            file.path.file_stem().unwrap_or_default().to_string_lossy()
+                        ),
+                    },
+                    // Telegram may still take the original; rejected photos go as documents
+                    Err(e) => {
+                        output::print_warning(&format!(
+                            "cannot convert {}, sending the original: {}",
+                            file.path.display(),
+                            e
+                        ));
+                        file.open().await?
+                    }
+                }
+            }
+            None => file.open().await?,
+        };
+        Ok(self.seal(source))
+    }
 
-    // Pre-resolve all unique destinations
-    let mut chat_cache: std::collections::HashMap<String, ResolvedChat> =
-        std::collections::HashMap::new();
+    /// Check a photo against Telegram's limits before upload
+    ///
+    /// Returns the conversion to apply when the file is opened; photos that
+    /// can't be sent as photos are switched to documents.
+    async fn prepare_photo(
+        &self,
+        file: &ValidatedFile,
+        options: &mut SendOptions,
+    ) -> Option<Convert> {
+        let path = file.data_path()?;
+        // Encrypted files always go as documents
+        if self.encryption.is_some() || options.kind.resolve(&file.name()) != MediaKind::Photo {
+            return None;
+        }
+        let path = path.to_path_buf();
+        let resize = self.resize_photos;
+        let check = tokio::task::spawn_blocking(move || check_photo(&path, resize))
+            .await
+            .unwrap_or_else(|e| PhotoCheck::Document(e.to_string()));
+        match check {
+            PhotoCheck::Ok => None,
+            PhotoCheck::Convert(convert) => Some(convert),
+            PhotoCheck::Document(reason) => {
+                output::print_photo_as_document(&file.path, &reason);
+                options.kind = MediaKind::Document;
+                None
+            }
+        }
+    }
 
-    // Collect unique destinations
-    let mut destinations: Vec<(usize, String)> = Vec::new();
-    for (i, file) in files.iter().enumerate() {
-        let file_ctx = FileContext::from_path_with_context(&file.path, i, total);
-        let dest = if let Some(ref to_expr) = ctx.to {
-            eval_routing(to_expr, &file_ctx)
-        } else {
-            ctx.chat.clone().unwrap_or_default()
+    /// Send short muted MP4s as animations when the kind is left to `--as auto`
+    async fn detect_animation(&self, file: &ValidatedFile, options: &mut SendOptions) {
+        // Encrypted files always go as documents
+        let Some(path) = file.data_path() else {
+            return;
         };
-        destinations.push((i, dest));
+        if self.encryption.is_some() || options.kind != MediaKind::Auto {
+            return;
+        }
+        let path = path.to_path_buf();
+        let animation = tokio::task::spawn_blocking(move || is_animation(&path))
+            .await
+            .unwrap_or(false);
+        if animation {
+            options.kind = MediaKind::Animation;
+        }
     }
 
-    // Pre-resolve unique chats
-    let unique_dests: std::collections::HashSet<_> =
-        destinations.iter().map(|(_, d)| d.clone()).collect();
-    for dest in unique_dests {
-        if !chat_cache.contains_key(&dest) {
-            match resolve_chat(ctx.client, &dest).await {
+    /// Encrypt an upload source if requested (a fresh file key per call)
+    fn seal(&self, source: UploadSource) -> UploadSource {
+        let Some(key) = self.encryption else {
+            return source;
+        };
+        UploadSource {
+            reader: Box::new(EncryptReader::new(source.reader, key.file_key())),
+            size: source.size.map(encrypted_size),
+            name: format!("{}{}", source.name, ENCRYPTED_SUFFIX),
+        }
+    }
+
+    /// Resolve a destination, retrying flood waits and transient errors
+    async fn resolve(&self, dest: &str) -> Result<ResolvedChat> {
+        if let Some(chat) = self.discussion {
+            return Ok(chat.clone());
+        }
+        let chat = with_retry(
+            &self.retry,
+            self.gate,
+            || resolve_chat(self.client, dest),
+            |e, d| self.on_retry(e, d),
+        )
+        .await?;
+        // The replied-to message only exists in the chat of the link
+        if self.reply_chat.is_some() && chat.peer_id() != self.reply_chat {
+            bail!("the --reply-to link points to another chat");
+        }
+        Ok(chat)
+    }
+
+    /// Send options for a file; comments reply to the post's discussion thread
+    fn options(&self, file: &ValidatedFile, file_ctx: &FileContext) -> Result<SendOptions> {
+        let mut options = self.send.resolve(file, file_ctx)?;
+        if let Some(thread) = self.thread {
+            options.reply_to = Some(thread);
+        }
+        Ok(options)
+    }
+
+    /// Take a chat's next post slot for a file about to be sent, scheduling it with `--queue`
+    ///
+    /// Called in file order, once nothing stops the file from being sent, so
+    /// skipped and failed files don't use up `--every` / `--queue` slots.
+    fn take_post(&self, chat: &ResolvedChat, options: &mut SendOptions) -> Result<usize> {
+        let (post, queued) = self.send.take_slot(self.chat_key(chat), 1);
+        self.send
+            .queue(std::slice::from_mut(options), post, queued)?;
+        Ok(post)
+    }
+
+    /// Destination of a file: the `--to` expression, or `--chat`
+    fn destination(&self, file_ctx: &FileContext) -> String {
+        match self.to {
+            Some(ref to_expr) => eval_routing(to_expr, file_ctx),
+            None => self.chat.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Resolve each unique destination once (failures are reported and left out)
+    async fn resolve_all<'d>(
+        &self,
+        dests: impl IntoIterator<Item = &'d String>,
+    ) -> HashMap<String, ResolvedChat> {
+        let mut chats = HashMap::new();
+        for dest in dests {
+            if chats.contains_key(dest) {
+                continue;
+            }
+            match self.resolve(dest).await {
                 Ok(c) => {
-                    chat_cache.insert(dest, c);
+                    chats.insert(dest.clone(), c);
                 }
                 Err(e) => {
                     output::print_failure(&format!("Failed to resolve '{}': {}", dest, e));
                 }
             }
         }
+        chats
+    }
+
+    /// Report a retry and let the concurrency limiter back off
+    fn on_retry(&self, error: &anyhow::Error, delay: std::time::Duration) {
+        output::print_retry(error, delay);
+        if classify(error) != Failure::Fatal {
+            self.concurrency.on_throttle();
+        }
+    }
+
+    /// Destination key used in the upload index (Saved Messages maps to the account itself)
+    fn chat_key(&self, chat: &ResolvedChat) -> i64 {
+        chat.peer_id().unwrap_or(self.user_id)
+    }
+
+    /// Start a result record for a file sent to a resolved chat
+    fn record(&self, file: &ValidatedFile, chat: &ResolvedChat) -> FileRecord {
+        let mut record = FileRecord::new(file, self.user_id);
+        record.destination = Some(chat.name.clone());
+        record.destination_id = Some(chat.marked_id().unwrap_or(self.user_id));
+        record
     }
+}
+
+/// Fill in a record for a sent message
+///
+/// Scheduled messages are numbered apart from posted ones, so they get no link.
+fn mark_uploaded(
+    record: &mut FileRecord,
+    chat: &ResolvedChat,
+    msg_id: i32,
+    options: &SendOptions,
+    started: Instant,
+) {
+    record.message_id = Some(msg_id);
+    if options.schedule.is_some() {
+        record.status = FileStatus::Scheduled;
+        record.link = None;
+    } else {
+        record.status = FileStatus::Uploaded;
+        record.link = chat.message_link(msg_id);
+    }
+    record.duration_ms = started.elapsed().as_millis() as u64;
+}
+
+/// Fill in a record for a file skipped as duplicate of an earlier message
+fn mark_duplicate(record: &mut FileRecord, chat: &ResolvedChat, sent: Sent) {
+    record.status = FileStatus::Skipped;
+    record.message_id = Some(sent.msg_id);
+    record.link = if sent.scheduled {
+        None
+    } else {
+        chat.message_link(sent.msg_id)
+    };
+    record.error = Some("duplicate".to_string());
+}
+
+/// Result of checking a file against the upload index
+enum DedupCheck {
+    /// Not uploaded yet; carries the hash to record (if indexing is enabled)
+    Fresh(Option<String>),
+    /// Already sent to this destination, before or by another file of this run
+    Duplicate(Sent),
+    /// Being sent there by another file of this run; checked again once it is done
+    Deferred,
+}
 
-    // Use Arc<Mutex> for thread-safe stats
-    let stats_mutex = Arc::new(Mutex::new((0usize, 0usize))); // (success, failed)
+/// Hash a file for the upload index, and check it with `--skip-uploaded`
+async fn check_duplicate(
+    ctx: &UploadContext<'_>,
+    file: &ValidatedFile,
+    chat: &ResolvedChat,
+) -> DedupCheck {
+    let Some(index) = ctx.index else {
+        return DedupCheck::Fresh(None);
+    };
+    // Stdin and URLs are spooled to local files when skipping; archives aren't indexed
+    let Some(path) = file.data_path() else {
+        return DedupCheck::Fresh(None);
+    };
 
-    // Process files concurrently
+    let hash = match hash_file(path).await {
+        Ok(h) => h,
+        Err(e) => {
+            output::print_failure(&format!("Failed to hash file: {}", e));
+            return DedupCheck::Fresh(None);
+        }
+    };
+    // Without --skip-uploaded the hash is only recorded
+    if !ctx.skip_uploaded {
+        return DedupCheck::Fresh(Some(hash));
+    }
+
+    let chat_key = ctx.chat_key(chat);
+    match index.lookup(&hash, ctx.user_id, chat_key) {
+        Ok(Some(sent)) => return DedupCheck::Duplicate(sent),
+        Ok(None) => {}
+        Err(e) => output::print_failure(&format!("Upload index error: {}", e)),
+    }
+    match index.claim(&hash, ctx.user_id, chat_key) {
+        Claim::Owner => DedupCheck::Fresh(Some(hash)),
+        Claim::InFlight => DedupCheck::Deferred,
+        Claim::Sent(sent) => DedupCheck::Duplicate(sent),
+    }
+}
+
+/// Record a successful upload in the index
+///
+/// Scheduled messages are flagged: their IDs are numbered apart from posted ones.
+fn record_upload(
+    ctx: &UploadContext<'_>,
+    file: &ValidatedFile,
+    chat: &ResolvedChat,
+    hash: &str,
+    msg_id: i32,
+    options: &SendOptions,
+) {
+    let Some(index) = ctx.index else {
+        return;
+    };
+    let sent = Sent {
+        msg_id,
+        scheduled: options.schedule.is_some(),
+    };
+    index.finish(hash, ctx.user_id, ctx.chat_key(chat), sent);
+    let name = file
+        .path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if let Err(e) = index.record(hash, ctx.user_id, ctx.chat_key(chat), sent, &name) {
+        output::print_failure(&format!("Upload index error: {}", e));
+    }
+}
+
+/// Release the claim of a failed upload, so the content can be sent again
+fn release_upload(ctx: &UploadContext<'_>, chat: &ResolvedChat, hash: Option<&str>) {
+    if let (Some(index), Some(hash)) = (ctx.index, hash) {
+        index.release(hash, ctx.user_id, ctx.chat_key(chat));
+    }
+}
+
+/// Handle single file uploads with concurrency
+pub async fn upload_single_files(
+    ctx: &UploadContext<'_>,
+    files: &[ValidatedFile],
+    stats: &mut UploadStats,
+) -> Result<()> {
+    // Files whose content was in flight are checked again once it is sent
+    let mut pending: Vec<usize> = (0..files.len()).collect();
+    while !pending.is_empty() {
+        let deferred = upload_single_pass(ctx, files, &pending, stats).await?;
+        if deferred.len() == pending.len() {
+            bail!("Upload index claims were not released");
+        }
+        pending = deferred;
+    }
+    Ok(())
+}
+
+/// Upload the `pending` files (indices into `files`), returning those deferred
+async fn upload_single_pass(
+    ctx: &UploadContext<'_>,
+    files: &[ValidatedFile],
+    pending: &[usize],
+    stats: &mut UploadStats,
+) -> Result<Vec<usize>> {
+    let total = files.len();
+
+    // Collect destinations
+    let destinations: Vec<String> = pending
+        .iter()
+        .map(|&i| ctx.destination(&FileContext::from_file(&files[i], i, total)))
+        .collect();
+
+    // Pre-resolve unique chats
+    let chat_cache = ctx.resolve_all(&destinations).await;
+
+    // Upload files concurrently, send them in file order
+    let order = SendOrder::default();
     let caption_ref = ctx.caption.as_deref();
-    let _: Vec<_> = stream::iter(files.iter().enumerate())
-        .map(|(i, file)| {
-            let file_ctx = FileContext::from_path_with_context(&file.path, i, total);
-            let dest = if let Some(ref to_expr) = ctx.to {
-                eval_routing(to_expr, &file_ctx)
-            } else {
-                ctx.chat.clone().unwrap_or_default()
-            };
+    let records = stream::iter(pending.iter().enumerate())
+        .map(|(seq, &i)| {
+            let file = &files[i];
+            let file_ctx = FileContext::from_file(file, i, total);
+            let dest = destinations[seq].clone();
             let chat = chat_cache.get(&dest);
-            let stats_mutex = Arc::clone(&stats_mutex);
+            let ticket = order.ticket(seq);
+            let options = ctx.options(file, &file_ctx);
 
             async move {
-                output::print_progress(i, total, &file.path);
-
                 let Some(chat) = chat else {
-                    let mut s = stats_mutex.lock().await;
-                    s.1 += 1;
-                    return;
+                    ctx.progress.skip_file(file);
+                    return Some(
+                        FileRecord::new(file, ctx.user_id)
+                            .failed(format!("Failed to resolve '{}'", dest)),
+                    );
+                };
+
+                let mut record = ctx.record(file, chat);
+
+                let mut options = match options {
+                    Ok(options) => options,
+                    Err(e) => {
+                        output::print_failure(&e.to_string());
+                        ctx.progress.skip_file(file);
+                        return Some(record.failed(e.to_string()));
+                    }
+                };
+
+                let hash = match check_duplicate(ctx, file, chat).await {
+                    DedupCheck::Fresh(hash) => hash,
+                    DedupCheck::Duplicate(sent) => {
+                        output::print_duplicate(sent.msg_id);
+                        ctx.progress.skip_file(file);
+                        mark_duplicate(&mut record, chat, sent);
+                        return Some(record);
+                    }
+                    DedupCheck::Deferred => return None,
                 };
+                ctx.detect_animation(file, &mut options).await;
+                let photo = ctx.prepare_photo(file, &mut options).await;
+
+                // Live pacing and archives take their post slot before uploading:
+                // wait for the earlier posts, then for this one's time
+                let live = ctx.send.pace.as_ref().is_some_and(|p| p.is_live());
+                if live || matches!(file.source, FileSource::Archive(_)) {
+                    ticket.wait().await;
+                    let delay = ctx
+                        .take_post(chat, &mut options)
+                        .and_then(|post| ctx.send.delay(post));
+                    match delay {
+                        Ok(Some(delay)) => {
+                            output::print_next_post(&file.path, delay);
+                            tokio::time::sleep(delay).await;
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            output::print_failure(&e.to_string());
+                            ctx.progress.skip_file(file);
+                            release_upload(ctx, chat, hash.as_deref());
+                            return Some(record.failed(e.to_string()));
+                        }
+                    }
+                }
 
-                match upload_file(ctx.client, &file.path, chat, ctx.topic, caption_ref).await {
-                    Ok(msg) => {
-                        output::print_success(msg.id());
-                        let mut s = stats_mutex.lock().await;
-                        s.0 += 1;
+                let progress = ctx.progress.file(file);
+                let started = Instant::now();
+                // Options with the post's schedule, once the post slot is taken
+                let queued = OnceLock::new();
+                let result = match file.source {
+                    FileSource::Archive(ref spec) => {
+                        // Volumes are sent as soon as they are packed (after the ticket above)
+                        let _slot = ctx.concurrency.acquire().await;
+                        output::print_progress(i, total, &file.path);
+                        upload_archive(ctx, file, spec, chat, caption_ref, &options, &progress)
+                            .await
+                    }
+                    _ => {
+                        let (ticket, options, queued) = (&ticket, &options, &queued);
+                        upload_and_send(
+                            ctx,
+                            || async {
+                                let _slot = ctx.concurrency.acquire().await;
+                                output::print_progress(i, total, &file.path);
+                                progress.restart();
+                                upload_media(
+                                    ctx.client,
+                                    ctx.open(file, photo).await?,
+                                    options,
+                                    ctx.part_workers,
+                                    &progress,
+                                )
+                                .await
+                            },
+                            // The slot is free again; send once the earlier files are sent
+                            |media| async move {
+                                ticket.wait().await;
+                                // Taken once, even if the file is uploaded again
+                                let options = match queued.get() {
+                                    Some(options) => options,
+                                    None if live => queued.get_or_init(|| options.clone()),
+                                    None => {
+                                        let mut options = options.clone();
+                                        ctx.take_post(chat, &mut options)?;
+                                        queued.get_or_init(|| options)
+                                    }
+                                };
+                                send_uploaded(ctx, file, chat, media, caption_ref, options).await
+                            },
+                        )
+                        .await
+                    }
+                };
+                let options = queued.into_inner().unwrap_or(options);
+                ctx.progress.finish_file(&progress, result.is_ok());
+                if file.size().is_none() {
+                    record.bytes = progress.file.position();
+                }
+                match result {
+                    Ok(msg_id) => {
+                        output::print_success(msg_id);
+                        if let Some(hash) = hash {
+                            record_upload(ctx, file, chat, &hash, msg_id, &options);
+                        }
+                        mark_uploaded(&mut record, chat, msg_id, &options, started);
+                        ctx.concurrency.on_success(record.bytes);
+                        Some(record)
                     }
                     Err(e) => {
                         output::print_failure(&e.to_string());
-                        let mut s = stats_mutex.lock().await;
-                        s.1 += 1;
+                        release_upload(ctx, chat, hash.as_deref());
+                        record.duration_ms = started.elapsed().as_millis() as u64;
+                        Some(record.failed(e.to_string()))
                     }
                 }
             }
+            .map(move |record| record.ok_or(i))
         })
-        .buffer_unordered(ctx.concurrent)
-        .collect()
+        .buffer_unordered(ctx.concurrency.max() * UPLOAD_AHEAD);
+
+    // Update stats as each file finishes (this starts its hook)
+    let mut deferred = Vec::new();
+    let mut records = std::pin::pin!(records);
+    while let Some(record) = records.next().await {
+        match record {
+            Ok(record) => stats.add_record(record),
+            Err(i) => deferred.push(i),
+        }
+    }
+
+    deferred.sort_unstable();
+    Ok(deferred)
+}
+
+/// Upload a file, then send it, uploading again if Telegram lost the parts
+///
+/// The upload is retried here and `send` retries itself, so a failed send
+/// doesn't upload again. Only `FILE_PART_MISSING` goes back to the upload, at
+/// most `--retries` times.
+async fn upload_and_send<M, T, U, UF, S, SF>(
+    ctx: &UploadContext<'_>,
+    mut upload: U,
+    mut send: S,
+) -> Result<T>
+where
+    U: FnMut() -> UF,
+    UF: Future<Output = Result<M>>,
+    S: FnMut(M) -> SF,
+    SF: Future<Output = Result<T>>,
+{
+    let mut reuploads = 0;
+    loop {
+        let media =
+            with_retry(&ctx.retry, ctx.gate, &mut upload, |e, d| ctx.on_retry(e, d)).await?;
+        match send(media).await {
+            Err(e) if classify(&e) == Failure::Reupload && reuploads < ctx.retry.retries => {
+                reuploads += 1;
+                ctx.on_retry(&e, Duration::ZERO);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Send an uploaded file, resending a photo Telegram rejects as a document
+async fn send_uploaded(
+    ctx: &UploadContext<'_>,
+    file: &ValidatedFile,
+    chat: &ResolvedChat,
+    mut media: InputMedia,
+    caption: Option<&str>,
+    options: &SendOptions,
+) -> Result<i32> {
+    // Kept across retries, so a lost response doesn't post twice
+    let mut random_id = rand::random();
+    loop {
+        let result = with_retry(
+            &ctx.retry,
+            ctx.gate,
+            || send_media(ctx.client, chat, media.clone(), caption, options, random_id),
+            |e, d| ctx.on_retry(e, d),
+        )
         .await;
+        // Telegram rejected the photo; resend the uploaded file as a document
+        if let Err(ref e) = result {
+            if let Some(document) = is_photo_rejected(e)
+                .then(|| photo_as_document(&media, &file.name()))
+                .flatten()
+            {
+                output::print_photo_as_document(&file.path, &e.to_string());
+                media = document;
+                random_id = rand::random();
+                continue;
+            }
+        }
+        return result;
+    }
+}
 
-    // Update stats
-    let final_stats = stats_mutex.lock().await;
-    stats.add_success(final_stats.0);
-    stats.add_failed(final_stats.1);
+/// Upload an archive volume by volume, returning the first volume's message ID
+///
+/// Each volume is staged on disk, so its upload and send are retried like a file's.
+async fn upload_archive(
+    ctx: &UploadContext<'_>,
+    file: &ValidatedFile,
+    spec: &Arc<ArchiveSpec>,
+    chat: &ResolvedChat,
+    caption: Option<&str>,
+    options: &SendOptions,
+    progress: &Progress,
+) -> Result<i32> {
+    let mut volumes = Volumes::open(file, Arc::clone(spec));
+    let mut first: Option<i32> = None;
 
-    Ok(())
+    while let Some(source) = volumes.next().await {
+        let volume = StagedVolume::write(source).await?;
+        // Caption only on the first volume
+        let caption = if first.is_none() { caption } else { None };
+        let random_id = rand::random();
+        let msg_id = upload_and_send(
+            ctx,
+            || async {
+                progress.restart();
+                upload_media(
+                    ctx.client,
+                    ctx.seal(volume.open().await?),
+                    options,
+                    ctx.part_workers,
+                    progress,
+                )
+                .await
+            },
+            |media| {
+                with_retry(
+                    &ctx.retry,
+                    ctx.gate,
+                    move || {
+                        send_media(ctx.client, chat, media.clone(), caption, options, random_id)
+                    },
+                    |e, d| ctx.on_retry(e, d),
+                )
+            },
+        )
+        .await?;
+        if volumes.split() {
+            output::print_volume(volumes.count(), msg_id);
+        }
+        first.get_or_insert(msg_id);
+    }
+
+    first.ok_or_else(|| anyhow!("Archive produced no data"))
+}
+
+/// File waiting to be sent in an album
+#[derive(Clone)]
+struct AlbumItem<'a> {
+    file: &'a ValidatedFile,
+    file_ctx: &'a FileContext,
+    chat: &'a ResolvedChat,
+    /// Content hash to record (if indexing is enabled)
+    hash: Option<String>,
+    options: SendOptions,
+    /// Conversion to apply to a photo when it is opened
+    photo: Option<Convert>,
 }
 
 /// Handle media group uploads
+///
+/// Files are partitioned by destination and album-wide options, then grouped into
+/// albums by `--group-by` key and kind. Kinds that can't be in an album are
+/// sent as single messages in the same order.
 pub async fn upload_media_groups(
     ctx: &UploadContext<'_>,
     files: &[ValidatedFile],
     stats: &mut UploadStats,
 ) -> Result<()> {
-    // Filter files that support media group (photos/videos only)
-    let media_files: Vec<_> = files
+    let total = files.len();
+    let contexts: Vec<FileContext> = files
         .iter()
-        .filter(|f| is_media_group_supported(&f.path))
+        .enumerate()
+        .map(|(i, file)| FileContext::from_file(file, i, total))
         .collect();
 
-    let non_media_count = files.len() - media_files.len();
-    if non_media_count > 0 {
-        output::print_skipped_files(non_media_count, "not photo/video");
-        stats.add_failed(non_media_count);
+    // Files whose content was in flight are checked again once it is sent
+    let mut pending: Vec<usize> = (0..total).collect();
+    while !pending.is_empty() {
+        let deferred = upload_groups_pass(ctx, files, &contexts, &pending, stats).await?;
+        if deferred.len() == pending.len() {
+            bail!("Upload index claims were not released");
+        }
+        pending = deferred;
     }
+    Ok(())
+}
+
+/// Send the `pending` files (indices into `files`) as albums, returning those deferred
+async fn upload_groups_pass(
+    ctx: &UploadContext<'_>,
+    files: &[ValidatedFile],
+    contexts: &[FileContext],
+    pending: &[usize],
+    stats: &mut UploadStats,
+) -> Result<Vec<usize>> {
+    let destinations: Vec<String> = pending
+        .iter()
+        .map(|&i| ctx.destination(&contexts[i]))
+        .collect();
+    let chats = ctx.resolve_all(&destinations).await;
 
-    if media_files.is_empty() {
-        output::print_no_media_files();
-        return Ok(());
+    // Drop files that can't be sent, already uploaded, or with invalid options
+    let mut items: Vec<AlbumItem> = Vec::new();
+    let mut keys: Vec<String> = Vec::new();
+    let mut deferred = Vec::new();
+    for (&i, dest) in pending.iter().zip(&destinations) {
+        let (file, file_ctx) = (&files[i], &contexts[i]);
+        let Some(chat) = chats.get(dest) else {
+            ctx.progress.skip_file(file);
+            stats.add_record(
+                FileRecord::new(file, ctx.user_id).failed(format!("Failed to resolve '{}'", dest)),
+            );
+            continue;
+        };
+        let mut options = match ctx.options(file, file_ctx) {
+            Ok(o) => o,
+            Err(e) => {
+                output::print_failure(&e.to_string());
+                ctx.progress.skip_file(file);
+                stats.add_record(ctx.record(file, chat).failed(e.to_string()));
+                continue;
+            }
+        };
+        match check_duplicate(ctx, file, chat).await {
+            DedupCheck::Fresh(hash) => {
+                // May switch the file to an animation or a document, which changes its album
+                ctx.detect_animation(file, &mut options).await;
+                let photo = ctx.prepare_photo(file, &mut options).await;
+                let key = album_key(ctx.group_by.as_deref(), file, options.kind, file_ctx);
+                keys.push(partition_key(dest, &options, &key));
+                items.push(AlbumItem {
+                    file,
+                    file_ctx,
+                    chat,
+                    hash,
+                    options,
+                    photo,
+                });
+            }
+            DedupCheck::Duplicate(sent) => {
+                output::print_duplicate(sent.msg_id);
+                ctx.progress.skip_file(file);
+                let mut record = ctx.record(file, chat);
+                mark_duplicate(&mut record, chat, sent);
+                stats.add_record(record);
+            }
+            DedupCheck::Deferred => deferred.push(i),
+        }
     }
 
-    // Determine destination
-    let dest = if let Some(ref to_expr) = ctx.to {
-        let file_ctx =
-            FileContext::from_path_with_context(&media_files[0].path, 0, media_files.len());
-        eval_routing(to_expr, &file_ctx)
-    } else {
-        ctx.chat.clone().unwrap_or_default()
-    };
+    // Group by destination and album key, split into batches of MAX_MEDIA_GROUP_SIZE
+    // Media groups are sent sequentially to maintain order
+    let batches: Vec<Vec<AlbumItem>> = albums(&keys)
+        .into_iter()
+        .map(|album| album.iter().map(|&i| items[i].clone()).collect())
+        .collect();
+    let total_batches = batches.len();
 
-    // Resolve chat
-    let chat = match resolve_chat(ctx.client, &dest).await {
-        Ok(c) => c,
-        Err(e) => {
-            output::print_failure(&format!("Failed to resolve '{}': {}", dest, e));
-            stats.add_failed(media_files.len());
-            return Ok(());
+    for (batch_idx, batch) in batches.into_iter().enumerate() {
+        let chat = batch[0].chat;
+        let (post, queued) = ctx.send.take_slot(ctx.chat_key(chat), batch.len());
+        let mut options: Vec<SendOptions> = batch.iter().map(|i| i.options.clone()).collect();
+        let delay = ctx
+            .send
+            .queue(&mut options, post, queued)
+            .and_then(|()| ctx.send.delay(post));
+        let delay = match delay {
+            Ok(delay) => delay,
+            Err(e) => {
+                output::print_group_failure(&e.to_string());
+                for item in &batch {
+                    ctx.progress.skip_file(item.file);
+                    release_upload(ctx, chat, item.hash.as_deref());
+                    stats.add_record(ctx.record(item.file, chat).failed(e.to_string()));
+                }
+                continue;
+            }
+        };
+        if let Some(delay) = delay {
+            output::print_next_post(&batch[0].file.path, delay);
+            tokio::time::sleep(delay).await;
         }
-    };
 
-    let total_batches = (media_files.len() + MAX_MEDIA_GROUP_SIZE - 1) / MAX_MEDIA_GROUP_SIZE;
+        // Voice messages, animations and video notes can't be in an album
+        let single = batch[0]
+            .options
+            .kind
+            .resolve(&batch[0].file.name())
+            .album()
+            .is_none();
+        if single {
+            output::print_progress(batch_idx, total_batches, &batch[0].file.path);
+        } else {
+            output::print_group_progress(batch_idx, total_batches, batch.len());
+        }
+        let progress: Vec<_> = batch.iter().map(|i| ctx.progress.file(i.file)).collect();
+        let item_contexts: Vec<&FileContext> = batch.iter().map(|i| i.file_ctx).collect();
+        let captions = ctx.captions.captions(&item_contexts);
 
-    // Split into batches of MAX_MEDIA_GROUP_SIZE
-    // Media groups are sent sequentially to maintain order
-    for (batch_idx, batch) in media_files.chunks(MAX_MEDIA_GROUP_SIZE).enumerate() {
-        let batch_paths: Vec<&std::path::Path> = batch.iter().map(|f| f.path.as_path()).collect();
+        // Drawn once per album, so retries don't post it twice
+        let random_ids: Vec<i64> = batch.iter().map(|_| rand::random()).collect();
+        let started = Instant::now();
+        let results: Vec<Result<Option<i32>, String>> = if single {
+            let item = &batch[0];
+            let (caption, item_options, random_id) =
+                (captions[0].as_deref(), &options[0], random_ids[0]);
+            let result = upload_and_send(
+                ctx,
+                || async {
+                    progress[0].restart();
+                    upload_media(
+                        ctx.client,
+                        ctx.open(item.file, item.photo).await?,
+                        item_options,
+                        ctx.part_workers,
+                        &progress[0],
+                    )
+                    .await
+                },
+                |media| {
+                    with_retry(
+                        &ctx.retry,
+                        ctx.gate,
+                        move || {
+                            send_media(
+                                ctx.client,
+                                chat,
+                                media.clone(),
+                                caption,
+                                item_options,
+                                random_id,
+                            )
+                        },
+                        |e, d| ctx.on_retry(e, d),
+                    )
+                },
+            )
+            .await;
+            vec![result.map(Some).map_err(|e| e.to_string())]
+        } else {
+            let album = AlbumBatch {
+                items: &batch,
+                chat,
+                captions: &captions,
+                options: &options,
+                random_ids: &random_ids,
+                progress: &progress,
+            };
+            send_album_batch(ctx, album).await
+        };
+        for (p, result) in progress.iter().zip(&results) {
+            ctx.progress.finish_file(p, result.is_ok());
+        }
 
-        output::print_group_progress(batch_idx, total_batches, batch.len());
+        let sent = results.iter().filter(|r| r.is_ok()).count();
+        match results.first() {
+            Some(Ok(Some(msg_id))) if single => output::print_success(*msg_id),
+            _ if sent > 0 => output::print_group_success(sent),
+            _ => {}
+        }
+        // Items failing together share one error
+        let mut errors: Vec<&String> = results.iter().filter_map(|r| r.as_ref().err()).collect();
+        errors.dedup();
+        for error in errors {
+            if single {
+                output::print_failure(error);
+            } else {
+                output::print_group_failure(error);
+            }
+        }
 
-        match upload_media_group(
-            ctx.client,
-            &batch_paths,
-            &chat,
-            ctx.topic,
-            ctx.caption.as_deref(),
+        for ((item, result), options) in batch.iter().zip(results).zip(&options) {
+            let mut record = ctx.record(item.file, chat);
+            record.duration_ms = started.elapsed().as_millis() as u64;
+            match result {
+                Ok(Some(msg_id)) => {
+                    if let Some(ref hash) = item.hash {
+                        record_upload(ctx, item.file, chat, hash, msg_id, options);
+                    }
+                    mark_uploaded(&mut record, chat, msg_id, options, started);
+                }
+                // Sent, but the message can't be matched for the index
+                Ok(None) => {
+                    record.status = match options.schedule {
+                        Some(_) => FileStatus::Scheduled,
+                        None => FileStatus::Uploaded,
+                    };
+                    release_upload(ctx, chat, item.hash.as_deref());
+                }
+                Err(e) => {
+                    release_upload(ctx, chat, item.hash.as_deref());
+                    record = record.failed(e);
+                }
+            }
+            stats.add_record(record);
+        }
+    }
+
+    Ok(deferred)
+}
+
+/// One album of a batch, with an entry per item in each slice
+struct AlbumBatch<'a> {
+    items: &'a [AlbumItem<'a>],
+    chat: &'a ResolvedChat,
+    captions: &'a [Option<String>],
+    options: &'a [SendOptions],
+    random_ids: &'a [i64],
+    progress: &'a [Progress],
+}
+
+/// Upload an album's files, then send the album and the photos Telegram rejected
+///
+/// Each stage (every upload, the album, every document) is retried on its own
+/// and never repeated once it succeeded, so a failed document doesn't post the
+/// album again. Returns the message ID (if known) or the error of each item.
+async fn send_album_batch(
+    ctx: &UploadContext<'_>,
+    album: AlbumBatch<'_>,
+) -> Vec<Result<Option<i32>, String>> {
+    let AlbumBatch {
+        items,
+        chat,
+        captions,
+        options,
+        random_ids,
+        progress,
+    } = album;
+    let mut results = vec![Ok(None); items.len()];
+    let mut media_items = Vec::with_capacity(items.len());
+    let mut documents = Vec::new();
+
+    for (i, item) in items.iter().enumerate() {
+        let prepared = upload_and_send(
+            ctx,
+            || async {
+                progress[i].restart();
+                upload_media(
+                    ctx.client,
+                    ctx.open(item.file, item.photo).await?,
+                    &options[i],
+                    ctx.part_workers,
+                    &progress[i],
+                )
+                .await
+            },
+            |media| {
+                with_retry(
+                    &ctx.retry,
+                    ctx.gate,
+                    move || {
+                        let spoiler = options[i].spoiler;
+                        prepare_album_item(
+                            ctx.client,
+                            chat,
+                            media.clone(),
+                            item.file.name(),
+                            spoiler,
+                        )
+                    },
+                    |e, d| ctx.on_retry(e, d),
+                )
+            },
         )
-        .await
-        {
-            Ok(count) => {
-                output::print_group_success(count);
-                stats.add_success(count);
+        .await;
+        match prepared {
+            Ok(AlbumMedia::Album(media)) => media_items.push((i, media)),
+            Ok(AlbumMedia::Document(document, e)) => {
+                output::print_photo_as_document(&item.file.path, &e.to_string());
+                documents.push((i, document));
             }
-            Err(e) => {
-                output::print_group_failure(&e.to_string());
-                stats.add_failed(batch.len());
+            // Nothing is posted unless every file is uploaded
+            Err(e) => return vec![Err(e.to_string()); items.len()],
+        }
+    }
+
+    if !media_items.is_empty() {
+        let (indices, media): (Vec<usize>, Vec<_>) = media_items.into_iter().unzip();
+        let album_captions: Vec<Option<String>> =
+            indices.iter().map(|&i| captions[i].clone()).collect();
+        let album_ids: Vec<i64> = indices.iter().map(|&i| random_ids[i]).collect();
+        // Silent, protect, schedule and reply apply to the whole album
+        let sent = with_retry(
+            &ctx.retry,
+            ctx.gate,
+            || {
+                send_album(
+                    ctx.client,
+                    chat,
+                    media.clone(),
+                    &album_captions,
+                    &options[indices[0]],
+                    &album_ids,
+                )
+            },
+            |e, d| ctx.on_retry(e, d),
+        )
+        .await;
+        match sent {
+            Ok(msg_ids) => {
+                for (&i, msg_id) in indices.iter().zip(msg_ids) {
+                    results[i] = Ok(msg_id);
+                }
             }
+            // The documents follow the album, so they aren't sent either
+            Err(e) => return vec![Err(e.to_string()); items.len()],
         }
     }
 
-    Ok(())
+    for (i, document) in documents {
+        let result = with_retry(
+            &ctx.retry,
+            ctx.gate,
+            || {
+                send_media(
+                    ctx.client,
+                    chat,
+                    document.clone(),
+                    captions[i].as_deref(),
+                    &options[i],
+                    random_ids[i],
+                )
+            },
+            |e, d| ctx.on_retry(e, d),
+        )
+        .await;
+        results[i] = result.map(Some).map_err(|e| e.to_string());
+    }
+    results
+}
+
+/// Files that are safe to clean up after `--rm` / `--move-to`
+///
+/// An account confirms a file when it uploaded it or found it already sent
+/// (duplicate). `accounts` is the number of accounts that uploaded; accounts
+/// skipped as not logged in don't hold files back.
+pub fn confirmed_files<'a>(
+    files: &'a [ValidatedFile],
+    records: &[FileRecord],
+    accounts: usize,
+    policy: RmPolicy,
+) -> Vec<&'a ValidatedFile> {
+    let mut confirmed: HashMap<&str, HashSet<i64>> = HashMap::new();
+    // A skip only confirms a file when it names the message already sent
+    let confirms = |r: &&FileRecord| match r.status {
+        FileStatus::Uploaded | FileStatus::Scheduled => true,
+        FileStatus::Skipped => r.message_id.is_some(),
+        FileStatus::Failed => false,
+    };
+    for record in records.iter().filter(confirms) {
+        confirmed
+            .entry(record.path.as_str())
+            .or_default()
+            .insert(record.account);
+    }
+
+    let required = match policy {
+        RmPolicy::Any => 1,
+        RmPolicy::All => accounts.max(1),
+    };
+    files
+        .iter()
+        .filter(|file| {
+            confirmed
+                .get(file.display().as_str())
+                .is_some_and(|accounts| accounts.len() >= required)
+        })
+        .collect()
 }
 
-/// Remove uploaded files
-pub fn remove_files(files: &[ValidatedFile]) -> usize {
+/// Remove uploaded files, or move them into `move_to`
+pub fn remove_files(files: &[&ValidatedFile], move_to: Option<&Path>) -> usize {
+    if let Some(dir) = move_to {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            output::print_remove_failure(&e.to_string());
+            return 0;
+        }
+    }
+
     let mut removed = 0;
     for file in files {
-        if let Err(e) = std::fs::remove_file(&file.path) {
-            output::print_remove_failure(&e.to_string());
-        } else {
-            removed += 1;
+        // Spooled copies are temp files, removed with the spool
+        let paths: Vec<&Path> = match file.source {
+            FileSource::Local => vec![&file.path],
+            FileSource::Archive(ref spec) => {
+                spec.entries.iter().map(|(p, _)| p.as_path()).collect()
+            }
+            _ => Vec::new(),
+        };
+        for path in paths {
+            let result = match move_to {
+                Some(dir) => move_file(path, dir),
+                None => std::fs::remove_file(path),
+            };
+            if let Err(e) = result {
+                output::print_remove_failure(&format!("{}: {}", path.display(), e));
+            } else {
+                removed += 1;
+            }
         }
     }
     removed
 }
+
+/// Move a file into a directory, renaming it (`name (1).ext`) if taken
+fn move_file(path: &Path, dir: &Path) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default();
+    let mut target = dir.join(name);
+    let mut n = 1;
+    while target.exists() {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        target = dir.join(match path.extension() {
+            Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+            None => format!("{} ({})", stem, n),
+        });
+        n += 1;
+    }
+
+    // Rename fails across filesystems; copy and delete instead
+    if std::fs::rename(path, &target).is_err() {
+        std::fs::copy(path, &target)?;
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grammers_tl_types as tl;
+
+    fn channel() -> ResolvedChat {
+        ResolvedChat {
+            input_peer: tl::types::InputPeerChannel {
+                channel_id: 123,
+                access_hash: 0,
+            }
+            .into(),
+            name: "News".to_string(),
+            username: Some("news".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_mark_uploaded_scheduled() {
+        let chat = channel();
+        let file = ValidatedFile::local("/tmp/a.jpg");
+
+        let mut record = FileRecord::new(&file, 1);
+        mark_uploaded(
+            &mut record,
+            &chat,
+            7,
+            &SendOptions::default(),
+            Instant::now(),
+        );
+        assert!(record.status == FileStatus::Uploaded);
+        assert_eq!(record.link.as_deref(), Some("https://t.me/news/7"));
+
+        let scheduled = SendOptions {
+            schedule: Some(2_000_000_000),
+            ..Default::default()
+        };
+        let mut record = FileRecord::new(&file, 1);
+        mark_uploaded(&mut record, &chat, 3, &scheduled, Instant::now());
+        assert!(record.status == FileStatus::Scheduled);
+        assert_eq!(record.message_id, Some(3));
+        assert_eq!(record.link, None);
+    }
+
+    #[test]
+    fn test_confirmed_files() {
+        let files = vec![
+            ValidatedFile::local("/tmp/a.jpg"),
+            ValidatedFile::local("/tmp/b.jpg"),
+            ValidatedFile::local("/tmp/c.jpg"),
+        ];
+        let mut uploaded = FileRecord::new(&files[0], 1);
+        uploaded.status = FileStatus::Uploaded;
+        let mut duplicate = FileRecord::new(&files[1], 1);
+        mark_duplicate(
+            &mut duplicate,
+            &channel(),
+            Sent {
+                msg_id: 4,
+                scheduled: false,
+            },
+        );
+        // A skip without a message confirms nothing
+        let mut skipped = FileRecord::new(&files[2], 1);
+        skipped.status = FileStatus::Skipped;
+
+        let records = [uploaded, duplicate, skipped];
+        let confirmed = confirmed_files(&files, &records, 1, RmPolicy::Any);
+        let paths: Vec<_> = confirmed.iter().map(|f| f.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["/tmp/a.jpg", "/tmp/b.jpg"]);
+        assert!(confirmed_files(&files, &records, 2, RmPolicy::All).is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+//! Shell hooks (`--on-success`, `--on-failure`, `--on-complete`)
+//!
+//! Per-file hooks run once per file and account as soon as its result is
+//! known, with the result in `TDLR_*` environment variables. The end-of-run
+//! hook gets the JSON summary on stdin.
+
+use super::output;
+use super::report::{FileRecord, FileStatus};
+use crate::cli::UploadArgs;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::process::Stdio;
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+/// Hook commands from the command line
+#[derive(Default)]
+pub struct Hooks {
+    on_success: Option<String>,
+    on_failure: Option<String>,
+    on_complete: Option<String>,
+    /// Per-file hooks still running
+    running: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Summary passed to `--on-complete`
+#[derive(Serialize)]
+struct Summary<'a> {
+    success: usize,
+    failed: usize,
+    skipped: usize,
+    files: &'a [FileRecord],
+}
+
+impl Hooks {
+    pub fn new(args: &UploadArgs) -> Self {
+        Self {
+            on_success: args.on_success.clone(),
+            on_failure: args.on_failure.clone(),
+            on_complete: args.on_complete.clone(),
+            running: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Start the per-file hook for a result (duplicates count as success)
+    pub fn file(&self, record: &FileRecord) {
+        let hook = match record.status {
+            FileStatus::Uploaded | FileStatus::Scheduled | FileStatus::Skipped => &self.on_success,
+            FileStatus::Failed => &self.on_failure,
+        };
+        let Some(cmd) = hook.clone() else {
+            return;
+        };
+        let env = file_env(record);
+        let task = tokio::spawn(async move { report(run(&cmd, &env, None).await) });
+        self.running.lock().unwrap().push(task);
+    }
+
+    /// Wait for the per-file hooks started so far
+    pub async fn wait(&self) {
+        let running = std::mem::take(&mut *self.running.lock().unwrap());
+        for task in running {
+            let _ = task.await;
+        }
+    }
+
+    /// Run the end-of-run hook with the JSON summary on stdin
+    pub async fn complete(
+        &self,
+        success: usize,
+        failed: usize,
+        skipped: usize,
+        records: &[FileRecord],
+    ) {
+        let Some(ref cmd) = self.on_complete else {
+            return;
+        };
+        let summary = Summary {
+            success,
+            failed,
+            skipped,
+            files: records,
+        };
+        match serde_json::to_vec_pretty(&summary) {
+            Ok(json) => report(run(cmd, &[], Some(json)).await),
+            Err(e) => output::print_hook_failure(&e.to_string()),
+        }
+    }
+}
+
+/// Environment for a per-file hook
+fn file_env(record: &FileRecord) -> Vec<(&'static str, String)> {
+    let status = match record.status {
+        FileStatus::Uploaded => "uploaded",
+        FileStatus::Scheduled => "scheduled",
+        FileStatus::Failed => "failed",
+        FileStatus::Skipped => "skipped",
+    };
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    vec![
+        ("TDLR_PATH", record.path.clone()),
+        ("TDLR_STATUS", status.to_string()),
+        ("TDLR_ACCOUNT", record.account.to_string()),
+        ("TDLR_DESTINATION", optional(record.destination.clone())),
+        (
+            "TDLR_DESTINATION_ID",
+            optional(record.destination_id.map(|id| id.to_string())),
+        ),
+        (
+            "TDLR_MESSAGE_ID",
+            optional(record.message_id.map(|id| id.to_string())),
+        ),
+        ("TDLR_LINK", optional(record.link.clone())),
+        ("TDLR_BYTES", record.bytes.to_string()),
+        ("TDLR_ERROR", optional(record.error.clone())),
+    ]
+}
+
+/// Run a hook through the shell, optionally writing `stdin` to it
+async fn run(cmd: &str, env: &[(&str, String)], stdin: Option<Vec<u8>>) -> Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(cmd);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(cmd);
+        c
+    };
+    command
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+
+    let mut child = command.spawn()?;
+    if let (Some(data), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // The hook may exit without reading; that is not an error
+        let _ = pipe.write_all(&data).await;
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        bail!("'{}' exited with {}", cmd, status);
+    }
+    Ok(())
+}
+
+/// Print a hook error; hooks never fail the upload
+fn report(result: Result<()>) {
+    if let Err(e) = result {
+        output::print_hook_failure(&e.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::upload::file::ValidatedFile;
+
+    #[test]
+    fn test_file_env() {
+        let mut record = FileRecord::new(&ValidatedFile::local("/tmp/a.mp4"), 42);
+        record.status = FileStatus::Uploaded;
+        record.message_id = Some(7);
+        let env = file_env(&record);
+        let get = |key| env.iter().find(|(k, _)| *k == key).map(|(_, v)| v.as_str());
+        assert_eq!(get("TDLR_PATH"), Some("/tmp/a.mp4"));
+        assert_eq!(get("TDLR_STATUS"), Some("uploaded"));
+        assert_eq!(get("TDLR_ACCOUNT"), Some("42"));
+        assert_eq!(get("TDLR_MESSAGE_ID"), Some("7"));
+        assert_eq!(get("TDLR_ERROR"), Some(""));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run() {
+        let env = [("TDLR_STATUS", "failed".to_string())];
+        assert!(run("test \"$TDLR_STATUS\" = failed", &env, None)
+            .await
+            .is_ok());
+        assert!(run("exit 3", &[], None).await.is_err());
+        let stdin = Some(b"{\"success\": 1}".to_vec());
+        assert!(run("grep -q success", &[], stdin).await.is_ok());
+    }
+}
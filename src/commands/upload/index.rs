@@ -0,0 +1,224 @@
+//! Content-hash upload index for duplicate detection
+//!
+//! Stored as SQLite in `sessions/uploads.db`, keyed by
+//! (file SHA-256, account user_id, destination chat ID). Uploads of this run
+//! are also claimed in memory, so identical files in one run are sent once.
+//! Scheduled messages are recorded too, flagged as such: their IDs are
+//! numbered apart from posted messages.
+
+use crate::telegram::SessionManager;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use sqlite::{ConnectionThreadSafe, State};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const INDEX_FILE: &str = "uploads.db";
+
+/// Message a file's content was sent as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sent {
+    pub msg_id: i32,
+    /// A scheduled message, numbered apart from posted ones
+    pub scheduled: bool,
+}
+
+/// Result of claiming an upload for this run
+#[derive(Debug, PartialEq, Eq)]
+pub enum Claim {
+    /// Nobody sent this content yet; the caller sends it
+    Owner,
+    /// Another file with the same content is being sent
+    InFlight,
+    /// Another file with the same content was sent in this run
+    Sent(Sent),
+}
+
+/// Local index of uploaded file hashes
+pub struct UploadIndex {
+    conn: ConnectionThreadSafe,
+    /// Uploads of this run by (hash, account, chat); `None` while in flight
+    claimed: Mutex<HashMap<(String, i64, i64), Option<Sent>>>,
+}
+
+impl UploadIndex {
+    /// Open (or create) the index under the sessions directory
+    pub fn open() -> Result<Self> {
+        SessionManager::ensure_dir()?;
+        Self::open_at(index_path())
+    }
+
+    /// Open (or create) the index at a path
+    fn open_at(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = sqlite::Connection::open_thread_safe(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS uploads (
+                hash TEXT NOT NULL,
+                account INTEGER NOT NULL,
+                chat INTEGER NOT NULL,
+                msg_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                uploaded_at INTEGER NOT NULL,
+                scheduled INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (hash, account, chat)
+            )",
+        )?;
+        // Indexes created before scheduled messages were recorded
+        if !has_column(&conn, "uploads", "scheduled")? {
+            conn.execute("ALTER TABLE uploads ADD COLUMN scheduled INTEGER NOT NULL DEFAULT 0")?;
+        }
+        Ok(Self {
+            conn,
+            claimed: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Claim an upload for this run, unless a file with the same content
+    /// is already being sent (or was sent) there
+    pub fn claim(&self, hash: &str, account: i64, chat: i64) -> Claim {
+        let mut claimed = self.claimed.lock().unwrap();
+        match claimed.get(&(hash.to_string(), account, chat)) {
+            Some(Some(sent)) => Claim::Sent(*sent),
+            Some(None) => Claim::InFlight,
+            None => {
+                claimed.insert((hash.to_string(), account, chat), None);
+                Claim::Owner
+            }
+        }
+    }
+
+    /// Complete a claim with the message the content was sent as
+    pub fn finish(&self, hash: &str, account: i64, chat: i64, sent: Sent) {
+        self.claimed
+            .lock()
+            .unwrap()
+            .insert((hash.to_string(), account, chat), Some(sent));
+    }
+
+    /// Give up a claim after a failed upload, so the content can be sent again
+    pub fn release(&self, hash: &str, account: i64, chat: i64) {
+        self.claimed
+            .lock()
+            .unwrap()
+            .remove(&(hash.to_string(), account, chat));
+    }
+
+    /// Look up the message of a previous upload of the same content
+    pub fn lookup(&self, hash: &str, account: i64, chat: i64) -> Result<Option<Sent>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT msg_id, scheduled FROM uploads WHERE hash = ? AND account = ? AND chat = ?",
+        )?;
+        stmt.bind((1, hash))?;
+        stmt.bind((2, account))?;
+        stmt.bind((3, chat))?;
+
+        if let State::Row = stmt.next()? {
+            Ok(Some(Sent {
+                msg_id: stmt.read::<i64, _>(0)? as i32,
+                scheduled: stmt.read::<i64, _>(1)? != 0,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Record a successful upload (or a scheduled message)
+    pub fn record(
+        &self,
+        hash: &str,
+        account: i64,
+        chat: i64,
+        sent: Sent,
+        name: &str,
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "INSERT OR REPLACE INTO uploads
+             (hash, account, chat, msg_id, name, uploaded_at, scheduled)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        stmt.bind((1, hash))?;
+        stmt.bind((2, account))?;
+        stmt.bind((3, chat))?;
+        stmt.bind((4, sent.msg_id as i64))?;
+        stmt.bind((5, name))?;
+        stmt.bind((6, chrono::Utc::now().timestamp()))?;
+        stmt.bind((7, sent.scheduled as i64))?;
+        stmt.next()?;
+        Ok(())
+    }
+}
+
+/// Whether a table has a column
+fn has_column(conn: &ConnectionThreadSafe, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(format!("PRAGMA table_info({})", table))?;
+    while let State::Row = stmt.next()? {
+        if stmt.read::<String, _>("name")? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Get index file path
+fn index_path() -> PathBuf {
+    SessionManager::sessions_dir().join(INDEX_FILE)
+}
+
+/// Compute the SHA-256 of a file as lowercase hex (runs on the blocking pool)
+pub async fn hash_file(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_path(&path)).await?
+}
+
+/// Compute the SHA-256 of a file as lowercase hex
+pub fn hash_path(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_lookup_and_claims() {
+        let index = UploadIndex::open_at(":memory:").unwrap();
+        assert_eq!(index.lookup("abc", 1, 2).unwrap(), None);
+        let posted = Sent {
+            msg_id: 42,
+            scheduled: false,
+        };
+        index.record("abc", 1, 2, posted, "a.jpg").unwrap();
+        assert_eq!(index.lookup("abc", 1, 2).unwrap(), Some(posted));
+        assert_eq!(index.lookup("abc", 1, 3).unwrap(), None);
+        let scheduled = Sent {
+            msg_id: 3,
+            scheduled: true,
+        };
+        index.record("abc", 1, 3, scheduled, "a.jpg").unwrap();
+        assert_eq!(index.lookup("abc", 1, 3).unwrap(), Some(scheduled));
+
+        assert_eq!(index.claim("def", 1, 2), Claim::Owner);
+        assert_eq!(index.claim("def", 1, 2), Claim::InFlight);
+        assert_eq!(index.claim("def", 9, 2), Claim::Owner);
+        index.release("def", 1, 2);
+        assert_eq!(index.claim("def", 1, 2), Claim::Owner);
+        let sent = Sent {
+            msg_id: 5,
+            scheduled: true,
+        };
+        index.finish("def", 1, 2, sent);
+        assert_eq!(index.claim("def", 1, 2), Claim::Sent(sent));
+    }
+}
@@ -2,15 +2,50 @@
 //!
 //! Module structure:
 //! - `upload.rs` - Command entry point
-//! - `file.rs` - File collection and filtering
+//! - `album.rs` - Album grouping (`--group-by`, document albums)
+//! - `archive.rs` - Packing directories into archives (`--archive`)
+//! - `concurrency.rs` - Adaptive concurrency control
+//! - `file.rs` - File collection (ignore files, hidden files, symlinks)
+//! - `filter.rs` - File filters (extensions, globs, size, dates, `--filter`)
 //! - `expr.rs` - Expression engine for captions and routing
 //! - `handler.rs` - Upload handlers (single/group)
+//! - `hooks.rs` - Shell hooks run per file and at the end of a run
+//! - `index.rs` - Content-hash index for skipping duplicates
+//! - `order.rs` - File sorting (`--sort`) and in-order sending
 //! - `output.rs` - Output formatting utilities
+//! - `photo.rs` - Photo checks and conversion (`--resize-photos`)
+//! - `plan.rs` - Upload planning for `--dry-run`
+//! - `progress.rs` - Shared progress display for concurrent uploads
+//! - `remote.rs` - Uploading directly from HTTP(S) URLs
+//! - `report.rs` - Machine-readable upload report (`--report`)
+//! - `send.rs` - Per-file send options and sidecars
+//! - `spool.rs` - Temp-file spooling for streamed sources
+//! - `stdin.rs` - Uploading data piped through stdin (`-p -`)
+//! - `video.rs` - Spotting short muted MP4s to send as animations
+//! - `watch.rs` - Directory watching for `--watch`
 
+mod album;
+mod archive;
+mod concurrency;
 pub mod expr;
 mod file;
+mod filter;
 mod handler;
+mod hooks;
+mod index;
+mod order;
 mod output;
+mod photo;
+mod plan;
+mod progress;
+mod remote;
+mod report;
+mod send;
+mod spool;
+mod stdin;
+#[allow(clippy::module_inception)]
 mod upload;
+mod video;
+mod watch;
 
 pub use upload::run;
@@ -0,0 +1,196 @@
+//! File order: `--sort` and sending in that order
+//!
+//! Files are uploaded in parallel, but each message is only sent once every
+//! earlier file has been sent (or has failed), so a series like ep01..ep20
+//! arrives in order.
+
+use super::expr::{eval_expr, FileContext};
+use super::file::ValidatedFile;
+use super::output;
+use crate::cli::SortOrder;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tokio::sync::watch;
+
+/// Sort key of a file
+enum SortKey {
+    Number(f64),
+    Text(String),
+}
+
+/// Sort files by `--sort` (ties are broken by path)
+pub fn sort_files(files: &mut Vec<ValidatedFile>, order: SortOrder, expr: Option<&str>) {
+    let total = files.len();
+    let mut keyed: Vec<(SortKey, ValidatedFile)> = files
+        .drain(..)
+        .enumerate()
+        .map(|(i, file)| (sort_key(order, expr, &file, i, total), file))
+        .collect();
+
+    keyed.sort_by(|(a, file_a), (b, file_b)| {
+        let path_a = file_a.path.to_string_lossy();
+        let path_b = file_b.path.to_string_lossy();
+        compare_keys(order, a, b).then_with(|| natural_cmp(&path_a, &path_b))
+    });
+    files.extend(keyed.into_iter().map(|(_, file)| file));
+}
+
+fn sort_key(
+    order: SortOrder,
+    expr: Option<&str>,
+    file: &ValidatedFile,
+    index: usize,
+    total: usize,
+) -> SortKey {
+    match order {
+        SortOrder::Name | SortOrder::Natural => {
+            SortKey::Text(file.path.to_string_lossy().into_owned())
+        }
+        SortOrder::Mtime => {
+            let mtime = std::fs::metadata(&file.path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0.0, |d| d.as_secs_f64());
+            SortKey::Number(mtime)
+        }
+        SortOrder::Size => SortKey::Number(file.size().unwrap_or(0) as f64),
+        SortOrder::Expr => {
+            let ctx = FileContext::from_file(file, index, total);
+            let value = match eval_expr(expr.unwrap_or_default(), &ctx) {
+                Ok(v) => v,
+                Err(e) => {
+                    output::print_warning(&format!("sort expression error: {}", e));
+                    String::new()
+                }
+            };
+            match value.trim().parse::<f64>() {
+                Ok(n) => SortKey::Number(n),
+                Err(_) => SortKey::Text(value),
+            }
+        }
+    }
+}
+
+/// Numbers before text; `--sort name` compares text character by character
+fn compare_keys(order: SortOrder, a: &SortKey, b: &SortKey) -> Ordering {
+    match (a, b) {
+        (SortKey::Number(a), SortKey::Number(b)) => a.total_cmp(b),
+        (SortKey::Number(_), SortKey::Text(_)) => Ordering::Less,
+        (SortKey::Text(_), SortKey::Number(_)) => Ordering::Greater,
+        (SortKey::Text(a), SortKey::Text(b)) if order == SortOrder::Name => a.cmp(b),
+        (SortKey::Text(a), SortKey::Text(b)) => natural_cmp(a, b),
+    }
+}
+
+/// Compare strings with digit runs compared as numbers (`ep2` < `ep10`)
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (digits_a, rest_a) = split_digits(a);
+                let (digits_b, rest_b) = split_digits(b);
+                let (num_a, num_b) = (
+                    digits_a.trim_start_matches('0'),
+                    digits_b.trim_start_matches('0'),
+                );
+                let ord = num_a
+                    .len()
+                    .cmp(&num_b.len())
+                    .then_with(|| num_a.cmp(num_b))
+                    .then_with(|| digits_a.len().cmp(&digits_b.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                (a, b) = (rest_a, rest_b);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            }
+        }
+    }
+}
+
+/// Split off the leading ASCII digits
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// Lets files be sent in order while their uploads finish in any order
+pub struct SendOrder {
+    /// Number of leading files that are done
+    next: watch::Sender<usize>,
+    /// Files done out of order
+    done: Mutex<BTreeSet<usize>>,
+}
+
+impl Default for SendOrder {
+    fn default() -> Self {
+        Self {
+            next: watch::channel(0).0,
+            done: Mutex::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl SendOrder {
+    /// Place of the file at `seq`; every file must take one
+    pub fn ticket(&self, seq: usize) -> Ticket<'_> {
+        Ticket { order: self, seq }
+    }
+
+    fn finish(&self, seq: usize) {
+        let mut done = self.done.lock().unwrap();
+        done.insert(seq);
+        self.next.send_modify(|next| {
+            while done.remove(next) {
+                *next += 1;
+            }
+        });
+    }
+}
+
+/// A file's place in the send order, released when dropped
+pub struct Ticket<'a> {
+    order: &'a SendOrder,
+    seq: usize,
+}
+
+impl Ticket<'_> {
+    /// Wait until every earlier file has been sent or given up
+    pub async fn wait(&self) {
+        let mut next = self.order.next.subscribe();
+        let _ = next.wait_for(|&next| next >= self.seq).await;
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.order.finish(self.seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec!["ep10.mp4", "ep2.mp4", "ep01.mp4", "ep1.mp4", "Ep3.mp4"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["Ep3.mp4", "ep1.mp4", "ep01.mp4", "ep2.mp4", "ep10.mp4"]
+        );
+    }
+}
@@ -1,11 +1,38 @@
 //! Output formatting utilities for upload command
 
+use super::plan::Plan;
+use crate::utils::{format_duration, format_size};
 use colored::Colorize;
+use indicatif::MultiProgress;
 use std::path::Path;
+use std::sync::Mutex;
+
+/// Progress display currently on screen, if any
+static ACTIVE_PROGRESS: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// Print a line without garbling the active progress bars
+macro_rules! out {
+    ($($arg:tt)*) => {
+        emit(|| println!($($arg)*))
+    };
+}
+
+/// Set (or clear) the progress display that output lines are printed around
+pub fn set_progress(multi: Option<MultiProgress>) {
+    *ACTIVE_PROGRESS.lock().unwrap() = multi;
+}
+
+/// Run a print with the progress bars hidden
+fn emit(print: impl FnOnce()) {
+    match ACTIVE_PROGRESS.lock().unwrap().as_ref() {
+        Some(multi) => multi.suspend(print),
+        None => print(),
+    }
+}
 
 /// Print upload progress header
 pub fn print_progress(index: usize, total: usize, path: &Path) {
-    println!(
+    out!(
         "\n[{}/{}] {} {}",
         index + 1,
         total,
@@ -16,36 +43,89 @@ pub fn print_progress(index: usize, total: usize, path: &Path) {
 
 /// Print upload success
 pub fn print_success(msg_id: i32) {
-    println!("{} Uploaded (msg_id: {})", "✓".green(), msg_id);
+    out!("{} Uploaded (msg_id: {})", "✓".green(), msg_id);
+}
+
+/// Print an uploaded archive volume
+pub fn print_volume(number: usize, msg_id: i32) {
+    out!("  {} Volume {} (msg_id: {})", "✓".green(), number, msg_id);
+}
+
+/// Print duplicate skip (already uploaded to this destination)
+pub fn print_duplicate(msg_id: i32) {
+    out!("{} Skipped (duplicate, msg_id: {})", "⊘".yellow(), msg_id);
+}
+
+/// Print a photo that is sent as a document instead
+pub fn print_photo_as_document(path: &Path, reason: &str) {
+    out!(
+        "{} {} goes as a document: {}",
+        "⚠".yellow(),
+        path.display(),
+        reason
+    );
+}
+
+/// Print a warning (e.g. an expression error) to stderr
+pub fn print_warning(message: &str) {
+    emit(|| eprintln!("{} Warning: {}", "⚠".yellow(), message));
 }
 
 /// Print upload failure
 pub fn print_failure(error: &str) {
-    println!("{} Failed: {}", "✗".red(), error.red());
+    out!("{} Failed: {}", "✗".red(), error.red());
+}
+
+/// Print retry notice (flood wait or transient error)
+pub fn print_retry(error: &anyhow::Error, delay: std::time::Duration) {
+    out!(
+        "{} {} — retrying in {}s",
+        "⟳".yellow(),
+        error,
+        delay.as_secs()
+    );
+}
+
+/// Print the wait before a paced post (`--every`)
+pub fn print_next_post(path: &Path, delay: std::time::Duration) {
+    out!(
+        "{} Next post {} in {}",
+        "⏳".cyan(),
+        path.display(),
+        format_duration(delay)
+    );
 }
 
 /// Print upload summary
-pub fn print_summary(success: usize, failed: usize) {
-    println!();
-    if failed == 0 {
-        println!(
+pub fn print_summary(success: usize, failed: usize, skipped: usize) {
+    out!();
+    if failed == 0 && skipped == 0 {
+        out!(
             "{} All {} file(s) uploaded successfully!",
             "✓".green(),
             success
         );
+    } else if failed == 0 {
+        out!(
+            "{}: {} success, {} skipped (duplicate)",
+            "Summary".cyan(),
+            success.to_string().green(),
+            skipped.to_string().yellow()
+        );
     } else {
-        println!(
-            "{}: {} success, {} failed",
+        out!(
+            "{}: {} success, {} failed, {} skipped (duplicate)",
             "Summary".cyan(),
             success.to_string().green(),
-            failed.to_string().red()
+            failed.to_string().red(),
+            skipped.to_string().yellow()
         );
     }
 }
 
 /// Print media group progress
 pub fn print_group_progress(batch_idx: usize, total_batches: usize, batch_size: usize) {
-    println!(
+    out!(
         "{} Uploading media group [{}/{}] ({} files)",
         "→".cyan(),
         batch_idx + 1,
@@ -56,44 +136,124 @@ pub fn print_group_progress(batch_idx: usize, total_batches: usize, batch_size:
 
 /// Print media group success
 pub fn print_group_success(count: usize) {
-    println!("{} Media group sent ({} files)", "✓".green(), count);
+    out!("{} Media group sent ({} files)", "✓".green(), count);
 }
 
 /// Print media group failure
 pub fn print_group_failure(error: &str) {
-    println!("{} Media group failed: {}", "✗".red(), error);
+    out!("{} Media group failed: {}", "✗".red(), error);
 }
 
 /// Print account header
 pub fn print_account_header(name: &str, user_id: i64) {
-    println!("\n{} Account: {} ({})", "→".cyan(), name, user_id);
+    out!("\n{} Account: {} ({})", "→".cyan(), name, user_id);
 }
 
 /// Print account not authorized warning
 pub fn print_account_not_authorized(user_id: i64) {
-    println!(
+    out!(
         "{} Account {} not authorized, skipping",
         "⚠".yellow(),
         user_id
     );
 }
 
-/// Print skipped files warning
-pub fn print_skipped_files(count: usize, reason: &str) {
-    println!("{} {} file(s) skipped ({})", "⚠".yellow(), count, reason);
+/// Print watch mode banner
+pub fn print_watching(paths: &[String]) {
+    out!(
+        "\n{} Watching {} for new files (Ctrl+C to stop)",
+        "👁".cyan(),
+        paths.join(", ")
+    );
+}
+
+/// Print a watch batch that failed as a whole (watching goes on)
+pub fn print_batch_failure(count: usize, error: &anyhow::Error) {
+    out!(
+        "{} Batch of {} new file(s) failed: {:#}",
+        "✗".red(),
+        count,
+        error
+    );
 }
 
-/// Print no media files warning
-pub fn print_no_media_files() {
-    println!("{} No media files to upload as group", "⚠".yellow());
+/// Print file removal (or move) result
+pub fn print_removed_files(count: usize, moved_to: Option<&Path>) {
+    match moved_to {
+        Some(dir) => out!(
+            "{} {} file(s) moved to {}",
+            "🗑".dimmed(),
+            count,
+            dir.display()
+        ),
+        None => out!("{} {} file(s) removed", "🗑".dimmed(), count),
+    }
 }
 
-/// Print file removal result
-pub fn print_removed_files(count: usize) {
-    println!("{} {} file(s) removed", "🗑".dimmed(), count);
+/// Print files kept because not every required upload succeeded
+pub fn print_kept_files(count: usize) {
+    out!(
+        "{} {} file(s) kept (not confirmed by the required accounts)",
+        "⚠".yellow(),
+        count
+    );
 }
 
 /// Print file removal failure
 pub fn print_remove_failure(error: &str) {
-    println!("  {} Failed to remove: {}", "⚠".yellow(), error);
+    out!("  {} Failed to remove: {}", "⚠".yellow(), error);
+}
+
+/// Print a failed hook command
+pub fn print_hook_failure(error: &str) {
+    out!("  {} Hook failed: {}", "⚠".yellow(), error);
+}
+
+/// Print report file location
+pub fn print_report_written(path: &Path) {
+    out!("{} Report written to {}", "✓".green(), path.display());
+}
+
+/// Print dry-run upload plan
+pub fn print_plan(plan: &Plan) {
+    let total = plan.files.len();
+    for (i, entry) in plan.files.iter().enumerate() {
+        out!("\n[{}/{}] {}", i + 1, total, entry.path);
+
+        let mode = match entry.batch {
+            Some(batch) => format!("{} #{}", entry.mode, batch),
+            None => entry.mode.clone(),
+        };
+        out!(
+            "  {} {}  {}  {}",
+            "→".cyan(),
+            entry.destination,
+            mode.yellow(),
+            format_size(entry.size).dimmed()
+        );
+
+        if let Some(ref err) = entry.route_error {
+            out!(
+                "  {} Routing error (falls back to 'me'): {}",
+                "⚠".yellow(),
+                err.red()
+            );
+        }
+        if let Some(ref caption) = entry.caption {
+            out!("  {} {}", "Caption:".dimmed(), caption);
+        }
+        if !entry.options.is_empty() {
+            out!("  {} {}", "Options:".dimmed(), entry.options.join(", "));
+        }
+        if let Some(ref err) = entry.options_error {
+            out!("  {} {}", "✗".red(), err.red());
+        }
+    }
+
+    out!(
+        "\n{}: {} file(s), {} (dry run, nothing uploaded)",
+        "Plan".cyan(),
+        plan.total_files,
+        format_size(plan.total_bytes)
+    );
 }
@@ -0,0 +1,229 @@
+//! Photo checks against Telegram's limits (`--resize-photos`)
+//!
+//! Telegram rejects photos over 10 MB, with width + height over 10000 or
+//! with an aspect ratio over 20:1. Such photos are sent as documents, or with
+//! `--resize-photos` downscaled and re-encoded as JPEG. WebP, BMP and HEIC
+//! photos are always converted to JPEG.
+//!
+//! HEIC is decoded with the native libheif library, so it needs the `heic`
+//! cargo feature. Without it HEIC photos are sent as documents.
+
+use crate::utils::format_size;
+use anyhow::{anyhow, bail, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageReader};
+use std::path::Path;
+
+/// Largest photo Telegram accepts
+const MAX_PHOTO_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Largest width + height Telegram accepts
+const MAX_DIMENSIONS_SUM: u32 = 10_000;
+
+/// Largest aspect ratio Telegram accepts
+const MAX_ASPECT_RATIO: f64 = 20.0;
+
+/// Longest side of downscaled photos (Telegram's own display size)
+const RESIZE_MAX_SIDE: u32 = 2560;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// A photo to re-encode as JPEG when it is opened for upload
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Convert {
+    /// Downscale to fit Telegram's limits
+    pub downscale: bool,
+}
+
+/// What to do with a photo before upload
+#[derive(Debug, PartialEq)]
+pub enum PhotoCheck {
+    /// Send as it is
+    Ok,
+    /// Re-encode as JPEG
+    Convert(Convert),
+    /// Send as a document, for the given reason
+    Document(String),
+}
+
+/// Check a local photo against Telegram's limits
+pub fn check_photo(path: &Path, resize: bool) -> PhotoCheck {
+    let ext = extension(path);
+    if is_heic(&ext) && !cfg!(feature = "heic") {
+        return PhotoCheck::Document("HEIC support is not built in (`heic` feature)".to_string());
+    }
+
+    let (w, h) = match dimensions(path, &ext) {
+        Ok(d) => d,
+        Err(e) => return PhotoCheck::Document(format!("cannot read image: {}", e)),
+    };
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+
+    let ratio = w.max(h) as f64 / w.min(h).max(1) as f64;
+    if ratio > MAX_ASPECT_RATIO {
+        return PhotoCheck::Document(format!("aspect ratio {}x{} is too extreme", w, h));
+    }
+    if w + h > MAX_DIMENSIONS_SUM {
+        return if resize {
+            PhotoCheck::Convert(Convert { downscale: true })
+        } else {
+            PhotoCheck::Document(format!("{}x{} is too large for a photo", w, h))
+        };
+    }
+    // Converted photos are checked for size after re-encoding
+    if matches!(ext.as_str(), "webp" | "bmp") || is_heic(&ext) {
+        return PhotoCheck::Convert(Convert { downscale: resize });
+    }
+    if size > MAX_PHOTO_SIZE {
+        return if resize {
+            PhotoCheck::Convert(Convert { downscale: true })
+        } else {
+            PhotoCheck::Document(format!(
+                "{} is over the 10 MB photo limit",
+                format_size(size)
+            ))
+        };
+    }
+    PhotoCheck::Ok
+}
+
+/// Decode a photo and re-encode it as JPEG
+///
+/// With `downscale`, the longest side is reduced to 2560 pixels.
+pub fn convert_photo(path: &Path, convert: Convert) -> Result<Vec<u8>> {
+    let mut img = decode(path).map_err(|e| anyhow!("cannot decode image: {}", e))?;
+    if convert.downscale && img.width().max(img.height()) > RESIZE_MAX_SIDE {
+        img = img.resize(RESIZE_MAX_SIDE, RESIZE_MAX_SIDE, FilterType::Lanczos3);
+    }
+
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY).encode_image(&img.to_rgb8())?;
+    if jpeg.len() as u64 > MAX_PHOTO_SIZE {
+        bail!(
+            "converted photo is {}, over the 10 MB limit",
+            format_size(jpeg.len() as u64)
+        );
+    }
+    Ok(jpeg)
+}
+
+/// Whether an extension is HEIC/HEIF, which the `image` crate can't read
+fn is_heic(ext: &str) -> bool {
+    matches!(ext, "heic" | "heif")
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// Width and height of a photo, without decoding it
+fn dimensions(path: &Path, ext: &str) -> Result<(u32, u32)> {
+    if is_heic(ext) {
+        return heic::dimensions(path);
+    }
+    Ok(ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_dimensions()?)
+}
+
+fn decode(path: &Path) -> Result<DynamicImage> {
+    if is_heic(&extension(path)) {
+        return heic::decode(path);
+    }
+    Ok(image::open(path)?)
+}
+
+/// HEIC decoding through libheif
+#[cfg(feature = "heic")]
+mod heic {
+    use anyhow::{anyhow, Result};
+    use image::{DynamicImage, RgbImage};
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+    use std::path::Path;
+
+    fn open(path: &Path) -> Result<HeifContext<'static>> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow!("path is not valid UTF-8"))?;
+        Ok(HeifContext::read_from_file(path)?)
+    }
+
+    pub fn dimensions(path: &Path) -> Result<(u32, u32)> {
+        let context = open(path)?;
+        let handle = context.primary_image_handle()?;
+        Ok((handle.width(), handle.height()))
+    }
+
+    /// Decode the primary image, with its rotation and cropping applied
+    pub fn decode(path: &Path) -> Result<DynamicImage> {
+        let context = open(path)?;
+        let handle = context.primary_image_handle()?;
+        let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
+        let planes = image.planes();
+        let plane = planes
+            .interleaved
+            .ok_or_else(|| anyhow!("no RGB data in HEIC image"))?;
+
+        // Rows may be padded past the pixel data
+        let row = plane.width as usize * 3;
+        let mut pixels = Vec::with_capacity(row * plane.height as usize);
+        for line in plane.data.chunks(plane.stride).take(plane.height as usize) {
+            pixels.extend_from_slice(line.get(..row).unwrap_or(line));
+        }
+        RgbImage::from_raw(plane.width, plane.height, pixels)
+            .map(DynamicImage::ImageRgb8)
+            .ok_or_else(|| anyhow!("HEIC image data is truncated"))
+    }
+}
+
+/// Stand-in when built without the `heic` feature (`check_photo` stops first)
+#[cfg(not(feature = "heic"))]
+mod heic {
+    use anyhow::{bail, Result};
+    use image::DynamicImage;
+    use std::path::Path;
+
+    pub fn dimensions(_path: &Path) -> Result<(u32, u32)> {
+        bail!("HEIC support is not built in (`heic` feature)")
+    }
+
+    pub fn decode(_path: &Path) -> Result<DynamicImage> {
+        bail!("HEIC support is not built in (`heic` feature)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_photo() {
+        let dir = std::env::temp_dir().join(format!("tdlr-photo-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let save = |name: &str, w, h| {
+            let path = dir.join(name);
+            image::RgbImage::new(w, h).save(&path).unwrap();
+            path
+        };
+
+        assert_eq!(check_photo(&save("ok.png", 40, 30), false), PhotoCheck::Ok);
+        assert!(matches!(
+            check_photo(&save("strip.png", 420, 20), true),
+            PhotoCheck::Document(_)
+        ));
+        let bmp = save("a.bmp", 40, 30);
+        assert_eq!(
+            check_photo(&bmp, false),
+            PhotoCheck::Convert(Convert { downscale: false })
+        );
+        assert!(convert_photo(&bmp, Convert { downscale: false })
+            .unwrap()
+            .starts_with(&[0xFF, 0xD8]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+//! Upload planning for `--dry-run`
+
+use super::album::{album_key, albums, partition_key, AlbumCaptions};
+use super::expr::{eval_expr, FileContext};
+use super::file::ValidatedFile;
+use super::index::hash_path;
+use super::photo::{check_photo, PhotoCheck};
+use super::send::{format_time, SendRules};
+use super::video::is_animation;
+use crate::cli::UploadArgs;
+use crate::crypto::ENCRYPTED_SUFFIX;
+use crate::telegram::upload::{MediaKind, SendOptions};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Planned upload of a single file
+#[derive(Serialize)]
+pub struct PlanEntry {
+    pub path: String,
+    pub size: u64,
+    /// Destination as produced by `--to` / `--chat` ("me" for Saved Messages)
+    pub destination: String,
+    /// Routing expression error (the real upload falls back to "me")
+    pub route_error: Option<String>,
+    pub caption: Option<String>,
+    /// Send options in effect: silent, spoiler, protect, schedule <date>
+    pub options: Vec<String>,
+    /// Send option error (the file would fail)
+    pub options_error: Option<String>,
+    /// Media kind (photo, video, document, ...; "photo (converted)" when re-encoded),
+    /// album, duplicate, or failed
+    pub mode: String,
+    /// Album (or single message) number in group mode, 1-based
+    pub batch: Option<usize>,
+}
+
+/// Full upload plan
+#[derive(Serialize)]
+pub struct Plan {
+    pub files: Vec<PlanEntry>,
+    pub total_files: usize,
+    pub total_bytes: u64,
+}
+
+/// Build the upload plan without touching the network
+pub fn build_plan(args: &UploadArgs, files: &[ValidatedFile]) -> Result<Plan> {
+    let total = files.len();
+    let send = SendRules::new(args)?;
+    let mut options = Vec::with_capacity(total);
+    let mut entries: Vec<PlanEntry> = files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| {
+            let file_ctx = FileContext::from_file(file, i, total);
+            let (destination, route_error) = route(args, &file_ctx);
+            let mut resolved = send.resolve(file, &file_ctx);
+            let options_error = resolved.as_ref().err().map(|e| e.to_string());
+            let mode = match resolved {
+                Ok(ref mut options) => plan_kind(args, file, &file_ctx, options),
+                Err(_) => "failed".to_string(),
+            };
+            options.push(resolved.ok());
+            PlanEntry {
+                path: file.display(),
+                size: file_ctx.size,
+                destination,
+                route_error,
+                caption: args.caption.clone(),
+                options: Vec::new(),
+                options_error,
+                mode,
+                batch: None,
+            }
+        })
+        .collect();
+
+    if args.skip_uploaded {
+        plan_duplicates(&mut entries, files, &mut options);
+    }
+    if args.group {
+        plan_groups(&mut entries, files, &options, args);
+    }
+    if send.pace.is_some() {
+        plan_queue(&mut entries, &mut options, &send);
+    }
+    for (entry, options) in entries.iter_mut().zip(&options) {
+        if let Some(options) = options {
+            entry.options = describe_options(options);
+        }
+    }
+
+    let total_bytes = entries.iter().map(|e| e.size).sum();
+    Ok(Plan {
+        total_files: entries.len(),
+        total_bytes,
+        files: entries,
+    })
+}
+
+/// Settle the media kind the way the uploader does, returning the plan's mode
+///
+/// Short muted MP4s go as animations and photos are checked against
+/// Telegram's limits; encrypted files are sent under their `.enc` name, so
+/// `--as auto` makes them documents.
+fn plan_kind(
+    args: &UploadArgs,
+    file: &ValidatedFile,
+    file_ctx: &FileContext,
+    options: &mut SendOptions,
+) -> String {
+    if args.encrypt {
+        let name = format!("{}{}", file_ctx.name, ENCRYPTED_SUFFIX);
+        return options.kind.resolve(&name).name().to_string();
+    }
+    let Some(path) = file.data_path() else {
+        return options.kind.resolve(&file_ctx.name).name().to_string();
+    };
+    if options.kind == MediaKind::Auto && is_animation(path) {
+        options.kind = MediaKind::Animation;
+    }
+    let kind = options.kind.resolve(&file_ctx.name);
+    if kind != MediaKind::Photo {
+        return kind.name().to_string();
+    }
+    match check_photo(path, args.resize_photos) {
+        PhotoCheck::Ok => kind.name().to_string(),
+        PhotoCheck::Convert(_) => format!("{} (converted)", kind.name()),
+        PhotoCheck::Document(_) => {
+            options.kind = MediaKind::Document;
+            MediaKind::Document.name().to_string()
+        }
+    }
+}
+
+/// Mark files repeating the content of an earlier file to the same destination
+///
+/// The uploader sends such files once; the copies are skipped and take no
+/// album place or post slot. Uploads of earlier runs need the account's
+/// index, so only duplicates within this run are found here.
+fn plan_duplicates(
+    entries: &mut [PlanEntry],
+    files: &[ValidatedFile],
+    options: &mut [Option<SendOptions>],
+) {
+    let mut seen = HashSet::new();
+    for ((entry, file), options) in entries.iter_mut().zip(files).zip(options.iter_mut()) {
+        // Failed files are never sent, so their content isn't claimed
+        if options.is_none() {
+            continue;
+        }
+        let Some(hash) = file.data_path().and_then(|p| hash_path(p).ok()) else {
+            continue;
+        };
+        if !seen.insert((entry.destination.clone(), hash)) {
+            entry.mode = "duplicate".to_string();
+            *options = None;
+        }
+    }
+}
+
+/// Apply album batching and captions (mirrors `upload_media_groups`)
+///
+/// Files whose options failed and duplicates are left out, as the uploader drops them.
+fn plan_groups(
+    entries: &mut [PlanEntry],
+    files: &[ValidatedFile],
+    options: &[Option<SendOptions>],
+    args: &UploadArgs,
+) {
+    let total = files.len();
+    let contexts: Vec<FileContext> = files
+        .iter()
+        .enumerate()
+        .map(|(i, f)| FileContext::from_file(f, i, total))
+        .collect();
+    let mut planned = Vec::new();
+    let mut keys = Vec::new();
+    for (i, options) in options.iter().enumerate() {
+        let Some(options) = options else {
+            continue;
+        };
+        let key = album_key(
+            args.group_by.as_deref(),
+            &files[i],
+            options.kind,
+            &contexts[i],
+        );
+        keys.push(partition_key(&entries[i].destination, options, &key));
+        planned.push(i);
+    }
+
+    let captions = AlbumCaptions::new(args);
+    for (batch, items) in albums(&keys).into_iter().enumerate() {
+        let items: Vec<usize> = items.into_iter().map(|i| planned[i]).collect();
+        let item_contexts: Vec<&FileContext> = items.iter().map(|&i| &contexts[i]).collect();
+        // Kinds that can't be in an album are sent on their own
+        let album = options[items[0]]
+            .as_ref()
+            .is_some_and(|o| o.kind.resolve(&contexts[items[0]].name).album().is_some());
+        for (i, caption) in items.into_iter().zip(captions.captions(&item_contexts)) {
+            let entry = &mut entries[i];
+            if album {
+                entry.mode = "album".to_string();
+            }
+            entry.batch = Some(batch + 1);
+            entry.caption = caption;
+        }
+    }
+}
+
+/// Apply `--queue` schedule times (mirrors the handlers: per destination, one per album)
+fn plan_queue(entries: &mut [PlanEntry], options: &mut [Option<SendOptions>], send: &SendRules) {
+    // Destination and size of each album
+    let mut albums: BTreeMap<usize, (String, usize)> = BTreeMap::new();
+    for entry in entries.iter() {
+        if let Some(batch) = entry.batch {
+            let album = albums
+                .entry(batch)
+                .or_insert_with(|| (entry.destination.clone(), 0));
+            album.1 += 1;
+        }
+    }
+
+    // Post number and messages queued before each album, per destination
+    let mut posted: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut slots: HashMap<usize, (usize, usize, usize)> = HashMap::new();
+    for (batch, (dest, len)) in albums {
+        let (post, queued) = posted.entry(dest).or_default();
+        slots.insert(batch, (*post, *queued, len));
+        *post += 1;
+        *queued += len;
+    }
+
+    for (entry, options) in entries.iter_mut().zip(options.iter_mut()) {
+        // Failed and duplicate files take no post slot
+        let Some(resolved) = options else {
+            continue;
+        };
+        let (post, queued, len) = match entry.batch {
+            Some(batch) => slots[&batch],
+            None => {
+                let (post, _) = posted.entry(entry.destination.clone()).or_default();
+                *post += 1;
+                (*post - 1, *post - 1, 1)
+            }
+        };
+        let mut queued_options = vec![resolved.clone(); len];
+        match send.queue(&mut queued_options, post, queued) {
+            Ok(()) => *resolved = queued_options.swap_remove(0),
+            Err(e) => {
+                entry.options_error = Some(e.to_string());
+                *options = None;
+            }
+        }
+    }
+}
+
+/// Evaluate the destination for a file, keeping routing errors visible
+fn route(args: &UploadArgs, file_ctx: &FileContext) -> (String, Option<String>) {
+    if let Some(ref link) = args.comment_on {
+        return (format!("comments on {}", link), None);
+    }
+    match args.to {
+        Some(ref to_expr) => match eval_expr(to_expr, file_ctx) {
+            Ok(dest) => (display_dest(&dest), None),
+            Err(e) => ("me".to_string(), Some(e.to_string())),
+        },
+        None => (display_dest(args.chat.as_deref().unwrap_or_default()), None),
+    }
+}
+
+/// Normalize empty destinations to "me"
+fn display_dest(dest: &str) -> String {
+    let dest = dest.trim();
+    if dest.is_empty() {
+        "me".to_string()
+    } else {
+        dest.to_string()
+    }
+}
+
+/// Names of the send options that are set
+fn describe_options(options: &SendOptions) -> Vec<String> {
+    let flags = [
+        (options.silent, "silent"),
+        (options.spoiler, "spoiler"),
+        (options.protect, "protect"),
+    ];
+    let mut names: Vec<String> = flags
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    if let Some(time) = options.schedule {
+        names.push(format!("schedule {}", format_time(time as i64)));
+    }
+    names
+}
@@ -0,0 +1,111 @@
+//! Shared progress display for concurrent uploads
+//!
+//! All file bars live in one `MultiProgress` with an overall bar at the
+//! bottom. When stdout is not a terminal the bars are hidden and only the
+//! plain status lines from `output.rs` are printed.
+
+use super::file::ValidatedFile;
+use super::output;
+use crate::telegram::upload::Progress;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Progress display for one upload pass
+pub struct UploadProgress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    total_files: usize,
+    done: AtomicUsize,
+}
+
+impl UploadProgress {
+    /// Create the display for a set of files
+    pub fn new(files: &[ValidatedFile]) -> Self {
+        let tty = std::io::stdout().is_terminal();
+        let multi = if tty {
+            MultiProgress::with_draw_target(ProgressDrawTarget::stdout())
+        } else {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        };
+
+        let total_bytes = files.iter().filter_map(|f| f.size()).sum();
+        let overall = multi.add(ProgressBar::new(total_bytes));
+        if let Ok(style) = ProgressStyle::default_bar().template(
+            "{msg} [{bar:40.green/blue}] {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}",
+        ) {
+            overall.set_style(style.progress_chars("█▓░"));
+        }
+
+        let progress = Self {
+            multi,
+            overall,
+            total_files: files.len(),
+            done: AtomicUsize::new(0),
+        };
+        progress.update_message();
+
+        if tty {
+            output::set_progress(Some(progress.multi.clone()));
+        }
+        progress
+    }
+
+    /// Add a bar for a file about to be uploaded
+    pub fn file(&self, file: &ValidatedFile) -> Progress {
+        // Streams of unknown size get a bar without a total
+        let (bar, template) = match file.size() {
+            Some(size) => (
+                ProgressBar::new(size),
+                "{msg:30!} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+            ),
+            None => (
+                ProgressBar::no_length(),
+                "{msg:30!} {spinner:.cyan} {bytes} ({binary_bytes_per_sec})",
+            ),
+        };
+        let bar = self.multi.insert_before(&self.overall, bar);
+        if let Ok(style) = ProgressStyle::default_bar().template(template) {
+            bar.set_style(style.progress_chars("█▓░"));
+        }
+        bar.set_message(file.name());
+
+        Progress {
+            file: bar,
+            overall: Some(self.overall.clone()),
+        }
+    }
+
+    /// Remove a file's bar; a failed file no longer counts towards the total bytes
+    pub fn finish_file(&self, progress: &Progress, ok: bool) {
+        if !ok {
+            progress.restart();
+            self.overall.dec_length(progress.file.length().unwrap_or(0));
+        }
+        self.multi.remove(&progress.file);
+        self.done.fetch_add(1, Ordering::SeqCst);
+        self.update_message();
+    }
+
+    /// Count a file that was not uploaded (e.g. duplicate or unresolved destination)
+    pub fn skip_file(&self, file: &ValidatedFile) {
+        self.overall.dec_length(file.size().unwrap_or(0));
+        self.done.fetch_add(1, Ordering::SeqCst);
+        self.update_message();
+    }
+
+    fn update_message(&self) {
+        self.overall.set_message(format!(
+            "{}/{} files",
+            self.done.load(Ordering::SeqCst),
+            self.total_files
+        ));
+    }
+}
+
+impl Drop for UploadProgress {
+    fn drop(&mut self) {
+        self.overall.finish();
+        output::set_progress(None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Uploading directly from HTTP(S) URLs
+//!
+//! The response body is streamed into the upload. The size comes from
+//! Content-Length and the name from Content-Disposition or the URL path.
+//! Servers that reject `HEAD` (common for presigned and CDN URLs) are probed
+//! with a one-byte ranged `GET` instead.
+
+use super::file::{FileSource, ValidatedFile};
+use crate::telegram::upload::UploadSource;
+use anyhow::{Context, Result};
+use futures::TryStreamExt;
+use reqwest::header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio_util::io::StreamReader;
+
+/// Name used when neither the headers nor the URL provide one
+const DEFAULT_NAME: &str = "download";
+
+/// Whether a `--path` entry is a URL
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Shared HTTP client
+fn http() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .user_agent(concat!("tdlr/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+/// Build an entry from the URL alone (no network, size unknown)
+pub fn url_file(url: &str) -> ValidatedFile {
+    ValidatedFile {
+        path: PathBuf::from(name_from_url(url).unwrap_or_else(|| DEFAULT_NAME.to_string())),
+        source: FileSource::Url {
+            url: url.to_string(),
+            size: None,
+        },
+    }
+}
+
+/// Ask the server for the size and name of a URL
+pub async fn probe(url: &str) -> Result<ValidatedFile> {
+    let head = http()
+        .head(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    let (headers, size) = match head {
+        // HEAD responses have no body, so read the header instead of `content_length()`
+        Ok(response) => {
+            let size = header_number(response.headers(), CONTENT_LENGTH);
+            (response.headers().clone(), size)
+        }
+        Err(_) => probe_range(url).await?,
+    };
+
+    let mut file = url_file(url);
+    if let Some(name) = name_from_headers(&headers) {
+        file.path = PathBuf::from(name);
+    }
+    file.source = FileSource::Url {
+        url: url.to_string(),
+        size,
+    };
+    Ok(file)
+}
+
+/// Probe with a `GET` of the first byte; the body is not read
+async fn probe_range(url: &str) -> Result<(HeaderMap, Option<u64>)> {
+    let response = http()
+        .get(url)
+        .header(RANGE, "bytes=0-0")
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Cannot reach {}", url))?;
+
+    let size = if response.status() == StatusCode::PARTIAL_CONTENT {
+        // "bytes 0-0/12345" (the total may be "*" when unknown)
+        response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit_once('/'))
+            .and_then(|(_, total)| total.parse().ok())
+    } else {
+        response.content_length()
+    };
+    Ok((response.headers().clone(), size))
+}
+
+/// A numeric header value
+fn header_number(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<u64> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Start downloading a URL for upload
+pub async fn open(url: &str, name: String) -> Result<UploadSource> {
+    let response = http()
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Cannot download {}", url))?;
+
+    let size = response.content_length();
+    let stream = response.bytes_stream().map_err(std::io::Error::other);
+    Ok(UploadSource {
+        reader: Box::new(StreamReader::new(Box::pin(stream))),
+        size,
+        name,
+    })
+}
+
+/// File name from a Content-Disposition header
+fn name_from_headers(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_DISPOSITION)?.to_str().ok()?;
+
+    let mut plain = None;
+    for param in value.split(';').map(str::trim) {
+        if let Some(encoded) = param.strip_prefix("filename*=") {
+            // RFC 5987: charset'language'percent-encoded
+            let encoded = encoded.splitn(3, '\'').nth(2)?;
+            return sanitize(&percent_encoding::percent_decode_str(encoded).decode_utf8_lossy());
+        }
+        if let Some(name) = param.strip_prefix("filename=") {
+            plain = sanitize(name.trim_matches('"'));
+        }
+    }
+    plain
+}
+
+/// File name from the last URL path segment
+fn name_from_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let segment = url.path_segments()?.next_back()?;
+    sanitize(&percent_encoding::percent_decode_str(segment).decode_utf8_lossy())
+}
+
+/// Keep only the final path component of a server-provided name
+fn sanitize(name: &str) -> Option<String> {
+    Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve one canned response per connection, by request method
+    async fn serve(head: &'static str, get: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = socket.read(&mut buf).await;
+                let response = if buf.starts_with(b"HEAD") { head } else { get };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/files/video%20clip.mp4?token=1", addr)
+    }
+
+    #[test]
+    fn test_name_from_url() {
+        let file = url_file("https://host/a/b/video%20clip.mp4?x=1");
+        assert_eq!(file.name(), "video clip.mp4");
+        assert_eq!(url_file("https://host/").name(), DEFAULT_NAME);
+    }
+
+    #[tokio::test]
+    async fn test_probe_and_open() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\
+             Content-Disposition: attachment; filename=\"../report.pdf\"\r\n\
+             Connection: close\r\n\r\nhello";
+        let url = serve(response, response).await;
+
+        let file = probe(&url).await.unwrap();
+        assert_eq!(file.name(), "report.pdf");
+        assert_eq!(file.size(), Some(5));
+
+        let mut source = open(&url, file.name()).await.unwrap();
+        let mut body = String::new();
+        source.reader.read_to_string(&mut body).await.unwrap();
+        assert_eq!(body, "hello");
+        assert_eq!(source.size, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_probe_without_head() {
+        let url = serve(
+            "HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-0/1234\r\n\
+             Content-Length: 1\r\nConnection: close\r\n\r\nh",
+        )
+        .await;
+
+        let file = probe(&url).await.unwrap();
+        assert_eq!(file.name(), "video clip.mp4");
+        assert_eq!(file.size(), Some(1234));
+    }
+}
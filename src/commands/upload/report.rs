@@ -0,0 +1,165 @@
+//! Machine-readable upload report (`--report report.json|csv`)
+
+use super::file::{InputFailure, ValidatedFile};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::path::Path;
+
+/// Final status of a file upload
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Uploaded,
+    /// Sent as a scheduled message; not posted yet
+    Scheduled,
+    Failed,
+    Skipped,
+}
+
+/// Result of uploading one file with one account
+#[derive(Clone, Serialize)]
+pub struct FileRecord {
+    pub path: String,
+    pub status: FileStatus,
+    /// Account user ID (0 for inputs that failed before any upload)
+    pub account: i64,
+    pub destination: Option<String>,
+    pub destination_id: Option<i64>,
+    pub message_id: Option<i32>,
+    pub link: Option<String>,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+impl FileRecord {
+    /// Create a record for a file; status defaults to failed until filled in
+    pub fn new(file: &ValidatedFile, account: i64) -> Self {
+        Self {
+            path: file.display(),
+            status: FileStatus::Failed,
+            account,
+            destination: None,
+            destination_id: None,
+            message_id: None,
+            link: None,
+            bytes: file.size().unwrap_or(0),
+            duration_ms: 0,
+            error: None,
+        }
+    }
+
+    /// Create a failed record for an input that could not be read
+    pub fn input_failed(failure: InputFailure) -> Self {
+        Self {
+            path: failure.path,
+            status: FileStatus::Failed,
+            account: 0,
+            destination: None,
+            destination_id: None,
+            message_id: None,
+            link: None,
+            bytes: 0,
+            duration_ms: 0,
+            error: Some(failure.error),
+        }
+    }
+
+    /// Mark as failed with an error message
+    pub fn failed(mut self, error: impl Into<String>) -> Self {
+        self.status = FileStatus::Failed;
+        self.error = Some(error.into());
+        self
+    }
+}
+
+/// CSV columns, in `FileRecord` field order
+const CSV_HEADER: [&str; 10] = [
+    "path",
+    "status",
+    "account",
+    "destination",
+    "destination_id",
+    "message_id",
+    "link",
+    "bytes",
+    "duration_ms",
+    "error",
+];
+
+/// Report file format
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl ReportFormat {
+    /// Detect the format from the file extension
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        match ext.as_str() {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("Unsupported report format '{}' (use .json or .csv)", ext),
+        }
+    }
+}
+
+/// Write the report; the format is chosen from the file extension
+pub fn write_report(path: &Path, records: &[FileRecord]) -> Result<()> {
+    match ReportFormat::from_path(path)? {
+        ReportFormat::Json => {
+            let content = serde_json::to_string_pretty(records)?;
+            std::fs::write(path, content)?;
+        }
+        ReportFormat::Csv => {
+            // The header is written even when no file was uploaded
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_path(path)?;
+            writer.write_record(CSV_HEADER)?;
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_header() {
+        let dir = std::env::temp_dir().join(format!("tdlr-report-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.csv");
+        let header = CSV_HEADER.join(",");
+
+        write_report(&path, &[]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", header)
+        );
+
+        let failure = InputFailure {
+            path: "a.txt".to_string(),
+            error: "missing".to_string(),
+        };
+        write_report(&path, &[FileRecord::input_failed(failure)]).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(lines.next(), Some(header.as_str()));
+        assert_eq!(lines.next(), Some("a.txt,failed,0,,,,,0,0,missing"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
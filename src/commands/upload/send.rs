@@ -0,0 +1,467 @@
+//! Per-file send options (`--silent`, `--spoiler`, `--protect`, `--schedule`, `--as`)
+//!
+//! Each flag applies to every file when given alone, or takes an expression
+//! evaluated per file (e.g. `--spoiler 'str::contains(name, "nsfw")'`).
+//! `--schedule` takes a date (`2025-01-31 18:00`, RFC 3339 or a Unix time)
+//! or an expression producing one. `--as` takes a media kind or an expression
+//! producing one (e.g. `if(size > 10*MB, "document", "auto")`).
+//!
+//! `--every` paces posts per destination: live (waiting between posts), or
+//! with `--queue` as scheduled messages one interval apart.
+//!
+//! A `<file>.tdlr.json` sidecar next to a file overrides the flags for it:
+//! `{"silent": true, "spoiler": false, "protect": true, "schedule": "2025-01-31 18:00", "as": "document"}`
+
+use super::expr::FileContext;
+use super::file::ValidatedFile;
+use crate::cli::UploadArgs;
+use crate::telegram::upload::{parse_message_link, MediaKind, SendOptions};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use evalexpr::{eval_with_context, Value};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Suffix of sidecar files (never uploaded themselves)
+pub const SIDECAR_SUFFIX: &str = ".tdlr.json";
+
+/// Telegram's limit of scheduled messages per chat
+pub const MAX_SCHEDULED: usize = 100;
+
+/// Date formats accepted by `--schedule` (local time)
+const DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Whether a path is a sidecar file
+pub fn is_sidecar(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.ends_with(SIDECAR_SUFFIX))
+}
+
+/// A boolean option from the command line
+enum Flag {
+    Off,
+    On,
+    Expr(String),
+}
+
+impl Flag {
+    fn new(arg: &Option<Option<String>>) -> Self {
+        match arg {
+            None => Self::Off,
+            Some(None) => Self::On,
+            Some(Some(expr)) => Self::Expr(expr.clone()),
+        }
+    }
+
+    fn eval(&self, ctx: &FileContext) -> Result<bool> {
+        match self {
+            Self::Off => Ok(false),
+            Self::On => Ok(true),
+            Self::Expr(expr) => match eval_with_context(expr, &ctx.to_eval_context()) {
+                Ok(Value::Boolean(b)) => Ok(b),
+                Ok(other) => bail!("Expression '{}' gave {}, not a boolean", expr, other),
+                Err(e) => Err(anyhow!("Expression error: {}", e)),
+            },
+        }
+    }
+}
+
+/// Overrides read from a `<file>.tdlr.json` sidecar
+#[derive(Deserialize, Default)]
+struct Sidecar {
+    silent: Option<bool>,
+    spoiler: Option<bool>,
+    protect: Option<bool>,
+    schedule: Option<String>,
+    #[serde(rename = "as")]
+    kind: Option<String>,
+}
+
+impl Sidecar {
+    fn path(file: &Path) -> PathBuf {
+        let mut path = file.as_os_str().to_owned();
+        path.push(SIDECAR_SUFFIX);
+        PathBuf::from(path)
+    }
+
+    /// Read the sidecar of a local file, if there is one
+    fn load(file: &ValidatedFile) -> Result<Self> {
+        if !file.is_local() {
+            return Ok(Self::default());
+        }
+        let path = Self::path(&file.path);
+        match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)
+                .map_err(|e| anyhow!("Invalid sidecar {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(anyhow!("Cannot read sidecar {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Posting pace for `--every`
+pub struct Pace {
+    every: Duration,
+    /// Time of the first post when queueing (`--queue`)
+    queue_start: Option<i64>,
+    started: Instant,
+}
+
+impl Pace {
+    /// Schedule time of a chat's n-th post (0-based), when queueing
+    pub fn schedule(&self, post: usize) -> Result<Option<i64>> {
+        let Some(start) = self.queue_start else {
+            return Ok(None);
+        };
+        i64::try_from(post)
+            .ok()
+            .and_then(|post| (self.every.as_secs() as i64).checked_mul(post))
+            .and_then(|offset| start.checked_add(offset))
+            .map(Some)
+            .ok_or_else(|| anyhow!("Schedule time is out of range"))
+    }
+
+    /// Whether posts are sent when due (not queued in Telegram)
+    pub fn is_live(&self) -> bool {
+        self.queue_start.is_none()
+    }
+
+    /// Time left until a chat's n-th post is due, when posting live
+    pub fn delay(&self, post: usize) -> Result<Option<Duration>> {
+        if self.queue_start.is_some() {
+            return Ok(None);
+        }
+        let due = u32::try_from(post)
+            .ok()
+            .and_then(|post| self.every.checked_mul(post))
+            .and_then(|offset| self.started.checked_add(offset))
+            .ok_or_else(|| anyhow!("Post time is out of range"))?;
+        Ok(due
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero()))
+    }
+}
+
+/// Send option rules from the command line
+pub struct SendRules {
+    silent: Flag,
+    spoiler: Flag,
+    protect: Flag,
+    /// Per-file schedule (`--schedule` without `--queue`)
+    schedule: Option<String>,
+    /// Media kind or kind expression (`--as`)
+    kind: Option<String>,
+    /// Message replied to: `--topic` or `--reply-to`
+    pub reply_to: Option<i32>,
+    pub pace: Option<Pace>,
+    /// Posts and queued messages so far per chat, shared by all accounts and batches
+    slots: Mutex<HashMap<i64, (usize, usize)>>,
+}
+
+impl SendRules {
+    pub fn new(args: &UploadArgs) -> Result<Self> {
+        let pace = args.every.map(|every| Pace {
+            every,
+            queue_start: None,
+            started: Instant::now(),
+        });
+
+        // With --queue, --schedule is the time of the first post
+        let (schedule, pace) = match pace {
+            Some(mut pace) if args.queue => {
+                let start = match args.schedule {
+                    Some(ref date) => parse_date(date).map_err(|_| {
+                        anyhow!("--queue needs --schedule to be a date (the first post)")
+                    })?,
+                    None => Local::now().timestamp() + pace.every.as_secs() as i64,
+                };
+                if start <= Local::now().timestamp() {
+                    bail!("--schedule {} is in the past", format_time(start));
+                }
+                pace.queue_start = Some(start);
+                (None, Some(pace))
+            }
+            pace => (args.schedule.clone(), pace),
+        };
+
+        Ok(Self {
+            silent: Flag::new(&args.silent),
+            spoiler: Flag::new(&args.spoiler),
+            protect: Flag::new(&args.protect),
+            schedule,
+            kind: args.send_as.clone(),
+            reply_to: match args.reply_to {
+                Some(ref target) => Some(parse_reply_to(target)?),
+                None => args.topic,
+            },
+            pace,
+            slots: Mutex::default(),
+        })
+    }
+
+    /// Take the next post slot of a chat for a post of `messages` messages
+    ///
+    /// Returns the post number and the messages queued there before it.
+    pub fn take_slot(&self, chat: i64, messages: usize) -> (usize, usize) {
+        let mut slots = self.slots.lock().unwrap();
+        let (post, queued) = slots.entry(chat).or_default();
+        let slot = (*post, *queued);
+        *post += 1;
+        *queued += messages;
+        slot
+    }
+
+    /// Time left until a chat's n-th post is due, when posting live with `--every`
+    pub fn delay(&self, post: usize) -> Result<Option<Duration>> {
+        match self.pace {
+            Some(ref pace) => pace.delay(post),
+            None => Ok(None),
+        }
+    }
+
+    /// Schedule a chat's n-th post when queueing (a sidecar schedule wins)
+    ///
+    /// `queued` is the number of messages this run queued for the chat; messages
+    /// scheduled there before the run are not counted, so Telegram may still
+    /// reject posts near the limit.
+    pub fn queue(&self, options: &mut [SendOptions], post: usize, queued: usize) -> Result<()> {
+        let time = match self.pace {
+            Some(ref pace) => pace.schedule(post)?,
+            None => None,
+        };
+        let Some(time) = time else {
+            return Ok(());
+        };
+        if queued + options.len() > MAX_SCHEDULED {
+            bail!(
+                "Telegram allows at most {} scheduled messages per chat",
+                MAX_SCHEDULED
+            );
+        }
+        let time = i32::try_from(time).map_err(|_| anyhow!("Schedule time is out of range"))?;
+        for options in options {
+            options.schedule.get_or_insert(time);
+        }
+        Ok(())
+    }
+
+    /// Options for one file: flags and expressions, then its sidecar
+    pub fn resolve(&self, file: &ValidatedFile, ctx: &FileContext) -> Result<SendOptions> {
+        let sidecar = Sidecar::load(file)?;
+
+        let schedule = match sidecar.schedule {
+            Some(ref date) => Some(parse_date(date)?),
+            None => match self.schedule {
+                Some(ref value) => eval_schedule(value, ctx)?,
+                None => None,
+            },
+        };
+        if let Some(time) = schedule {
+            if time <= Local::now().timestamp() {
+                bail!("Schedule time {} is in the past", format_time(time));
+            }
+        }
+
+        let kind = match sidecar.kind {
+            Some(ref kind) => kind.parse()?,
+            None => match self.kind {
+                Some(ref value) => eval_kind(value, ctx)?,
+                None => MediaKind::Auto,
+            },
+        };
+
+        Ok(SendOptions {
+            silent: sidecar.silent.map_or_else(|| self.silent.eval(ctx), Ok)?,
+            spoiler: sidecar.spoiler.map_or_else(|| self.spoiler.eval(ctx), Ok)?,
+            protect: sidecar.protect.map_or_else(|| self.protect.eval(ctx), Ok)?,
+            schedule: schedule
+                .map(|t| i32::try_from(t).map_err(|_| anyhow!("Schedule time is out of range")))
+                .transpose()?,
+            reply_to: self.reply_to,
+            kind,
+        })
+    }
+}
+
+/// Media kind for `--as`: a kind name, or an expression giving one
+fn eval_kind(value: &str, ctx: &FileContext) -> Result<MediaKind> {
+    if let Ok(kind) = value.parse() {
+        return Ok(kind);
+    }
+    match eval_with_context(value, &ctx.to_eval_context()) {
+        Ok(Value::String(s)) => s.parse(),
+        Ok(other) => bail!("Media kind expression gave {}, not a kind", other),
+        Err(e) => bail!(
+            "'{}' is neither a media kind nor a valid expression: {}",
+            value,
+            e
+        ),
+    }
+}
+
+/// Schedule time for `--schedule`: a date, or an expression giving one (empty for none)
+fn eval_schedule(value: &str, ctx: &FileContext) -> Result<Option<i64>> {
+    if let Ok(time) = parse_date(value) {
+        return Ok(Some(time));
+    }
+    let date = match eval_with_context(value, &ctx.to_eval_context()) {
+        Ok(Value::String(s)) => s,
+        Ok(Value::Int(i)) => i.to_string(),
+        Ok(Value::Empty) => return Ok(None),
+        Ok(other) => bail!("Schedule expression gave {}, not a date", other),
+        Err(e) => bail!(
+            "'{}' is neither a date nor a valid expression: {}",
+            value,
+            e
+        ),
+    };
+    if date.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_date(&date).map(Some)
+}
+
+/// Message ID from `--reply-to`: a number or a message link
+///
+/// A link's chat is checked against each destination when uploading.
+fn parse_reply_to(target: &str) -> Result<i32> {
+    target
+        .trim()
+        .parse()
+        .ok()
+        .or_else(|| parse_message_link(target).map(|(_, msg_id)| msg_id))
+        .ok_or_else(|| anyhow!("--reply-to expects a message ID or link, got '{}'", target))
+}
+
+/// Parse a local date, an RFC 3339 date or a Unix time
+pub fn parse_date(value: &str) -> Result<i64> {
+    let value = value.trim();
+    if let Ok(time) = value.parse::<i64>() {
+        return Ok(time);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.timestamp());
+    }
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        // A bare date means midnight
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .and_then(|date| Local.from_local_datetime(&date).earliest())
+        .map(|date| date.timestamp())
+        .ok_or_else(|| anyhow!("Invalid date '{}' (expected YYYY-MM-DD HH:MM)", value))
+}
+
+/// Format a Unix time as a local date
+pub fn format_time(time: i64) -> String {
+    Local
+        .timestamp_opt(time, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| time.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1700000000").unwrap(), 1_700_000_000);
+        assert_eq!(parse_date("2023-11-14T22:13:20Z").unwrap(), 1_700_000_000);
+        let local = parse_date("2030-01-31 18:00").unwrap();
+        assert_eq!(format_time(local), "2030-01-31 18:00:00");
+        let midnight = parse_date("2030-01-31").unwrap();
+        assert_eq!(format_time(midnight), "2030-01-31 00:00:00");
+        assert!(parse_date("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_sidecar_overrides_flags() {
+        let dir = std::env::temp_dir().join(format!("tdlr-sidecar-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let photo = dir.join("a.jpg");
+        std::fs::write(&photo, b"x").unwrap();
+        std::fs::write(
+            Sidecar::path(&photo),
+            r#"{"spoiler": false, "schedule": "2030-01-31 18:00"}"#,
+        )
+        .unwrap();
+
+        let rules = SendRules {
+            silent: Flag::On,
+            spoiler: Flag::On,
+            protect: Flag::Expr("ext == \"png\"".to_string()),
+            schedule: None,
+            kind: None,
+            reply_to: None,
+            pace: None,
+            slots: Mutex::default(),
+        };
+        let file = ValidatedFile::local(&photo);
+        let options = rules
+            .resolve(&file, &FileContext::from_file(&file, 0, 1))
+            .unwrap();
+        assert!(options.silent);
+        assert!(!options.spoiler);
+        assert!(!options.protect);
+        assert!(options.schedule.is_some());
+        assert!(is_sidecar(&Sidecar::path(&photo)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_pace_out_of_range() {
+        let every = Duration::from_secs(365 * 24 * 60 * 60);
+        let queued = Pace {
+            every,
+            queue_start: Some(2_000_000_000),
+            started: Instant::now(),
+        };
+        assert_eq!(
+            queued.schedule(1).unwrap(),
+            Some(2_000_000_000 + every.as_secs() as i64)
+        );
+        assert!(queued.schedule(usize::MAX).is_err());
+
+        let live = Pace {
+            every,
+            queue_start: None,
+            started: Instant::now(),
+        };
+        assert_eq!(live.delay(0).unwrap(), None);
+        assert!(live.delay(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_take_slot() {
+        let rules = SendRules {
+            silent: Flag::Off,
+            spoiler: Flag::Off,
+            protect: Flag::Off,
+            schedule: None,
+            kind: None,
+            reply_to: None,
+            pace: None,
+            slots: Mutex::default(),
+        };
+        assert_eq!(rules.take_slot(1, 3), (0, 0));
+        assert_eq!(rules.take_slot(2, 1), (0, 0));
+        assert_eq!(rules.take_slot(1, 1), (1, 3));
+        assert_eq!(rules.take_slot(1, 1), (2, 4));
+    }
+}
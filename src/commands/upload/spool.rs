@@ -0,0 +1,51 @@
+//! Temp-file spooling for sources that can't be re-read or sized up front
+
+use super::file::{FileSource, ValidatedFile};
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// Temp directory holding spooled data, removed on drop
+pub struct Spool {
+    dir: PathBuf,
+    count: usize,
+}
+
+impl Spool {
+    pub fn new() -> Self {
+        Self {
+            dir: std::env::temp_dir().join(format!("tdlr-spool-{}", std::process::id())),
+            count: 0,
+        }
+    }
+
+    /// Copy a file's data into a temp file
+    ///
+    /// The entry keeps its name and URL, so expressions, reports and hooks see
+    /// the original source rather than the temp file.
+    pub async fn add(&mut self, file: &ValidatedFile) -> Result<ValidatedFile> {
+        // One subdirectory per file so equal names don't collide
+        let dir = self.dir.join(self.count.to_string());
+        self.count += 1;
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let path = dir.join(file.name());
+        let mut source = file.open().await?;
+        let mut out = tokio::fs::File::create(&path).await?;
+        tokio::io::copy(&mut source.reader, &mut out).await?;
+
+        let url = match file.source {
+            FileSource::Url { ref url, .. } => Some(url.clone()),
+            _ => None,
+        };
+        Ok(ValidatedFile {
+            path: file.path.clone(),
+            source: FileSource::Spooled { data: path, url },
+        })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,52 @@
+//! Uploading data piped through stdin (`-p -`)
+//!
+//! Stdin is streamed straight to Telegram without knowing its size. It can
+//! only be read once, so it is spooled (see `spool.rs`) when the data is
+//! needed more than once (hashing for `--skip-uploaded`, several accounts).
+
+use super::file::{FileSource, ValidatedFile};
+use crate::telegram::upload::UploadSource;
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Path argument that selects stdin
+pub const STDIN_PATH: &str = "-";
+
+/// File name used when `--name` is not given
+const DEFAULT_NAME: &str = "stdin";
+
+/// Set once stdin has been handed out for reading
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Build the stdin entry if `-` is among the paths
+pub fn stdin_file(paths: &[String], name: Option<&str>) -> Result<Option<ValidatedFile>> {
+    match paths.iter().filter(|p| *p == STDIN_PATH).count() {
+        0 => Ok(None),
+        1 => {
+            // Keep only the file name part so it can't point elsewhere
+            let name = name
+                .and_then(|n| Path::new(n).file_name())
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| DEFAULT_NAME.to_string());
+            Ok(Some(ValidatedFile {
+                path: PathBuf::from(name),
+                source: FileSource::Stdin,
+            }))
+        }
+        _ => bail!("Stdin ('-') can only be given once"),
+    }
+}
+
+/// Open stdin for upload (only possible once)
+pub fn open(name: String) -> Result<UploadSource> {
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        bail!("Stdin was already consumed and cannot be read again");
+    }
+
+    Ok(UploadSource {
+        reader: Box::new(tokio::io::stdin()),
+        size: None,
+        name,
+    })
+}
@@ -1,38 +1,67 @@
 //! Upload command entry point
 
-use super::file::{collect_files, FileFilter};
+use super::album::AlbumCaptions;
+use super::archive::collect_archives;
+use super::concurrency::Concurrency;
+use super::file::{collect_files, InputFailure, ValidatedFile};
+use super::filter::FileFilter;
 use super::handler::{
-    remove_files, upload_media_groups, upload_single_files, UploadContext, UploadStats,
+    confirmed_files, remove_files, upload_media_groups, upload_single_files, UploadContext,
+    UploadStats,
 };
+use super::hooks::Hooks;
+use super::index::UploadIndex;
+use super::order::sort_files;
 use super::output;
-use crate::telegram::{pool, SessionManager};
-use anyhow::{bail, Result};
-
-/// Default concurrent upload count (max allowed by Telegram)
-const DEFAULT_CONCURRENT: usize = 10;
-
-pub async fn run(
-    paths: Vec<String>,
-    chat: Option<String>,
-    include: Option<Vec<String>>,
-    exclude: Option<Vec<String>>,
-    rm: bool,
-    topic: Option<i32>,
-    account: Option<Vec<i64>>,
-    all_accounts: bool,
-    caption: Option<String>,
-    to: Option<String>,
-    group: bool,
-) -> Result<()> {
-    if paths.is_empty() {
+use super::plan::build_plan;
+use super::progress::UploadProgress;
+use super::remote;
+use super::report::{write_report, FileRecord, ReportFormat};
+use super::send::SendRules;
+use super::spool::Spool;
+use super::stdin::stdin_file;
+use super::watch::DirWatcher;
+use crate::cli::UploadArgs;
+use crate::crypto::{self, MasterKey};
+use crate::telegram::upload::{
+    parse_message_link, resolve_chat, resolve_discussion, ResolvedChat, RetryPolicy,
+};
+use crate::telegram::{pool, SessionManager, TelegramClient};
+use anyhow::{anyhow, bail, Result};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub async fn run(args: UploadArgs) -> Result<()> {
+    if args.path.is_empty() {
         bail!("No paths specified");
     }
 
+    if args.dry_run {
+        return dry_run(&args);
+    }
+
+    // Validate the report format before uploading anything
+    if let Some(ref report) = args.report {
+        ReportFormat::from_path(report)?;
+    }
+
+    // Validate send options (e.g. --queue start) before uploading anything;
+    // pacing and queue slots are shared by all accounts and watch batches
+    let send = SendRules::new(&args)?;
+
+    // Ask for the passphrase before doing any work
+    let encryption = if args.encrypt {
+        let secret = crypto::load_secret(args.key_file.as_deref(), true)?;
+        Some(MasterKey::generate(&secret)?)
+    } else {
+        None
+    };
+
     // Get clients based on account selection
-    let clients = if all_accounts {
+    let clients = if args.all_accounts {
         pool().get_all().await?
-    } else if let Some(ids) = account {
-        pool().get_many(&ids).await?
+    } else if let Some(ref ids) = args.account {
+        pool().get_many(ids).await?
     } else {
         vec![pool().get_active().await?]
     };
@@ -42,18 +71,274 @@ pub async fn run(
     }
 
     // Build file filter and collect files
-    let filter = FileFilter::new(include, exclude);
-    let (files, initial_failed) = collect_files(&paths, &filter);
+    let filter = FileFilter::new(&args)?;
+    let (mut files, mut input_failures) = match args.archive {
+        Some(format) => collect_archives(&args, format, &filter),
+        None => collect_files(&args.path, &filter),
+    };
+    sort_files(&mut files, args.sort, args.sort_expr.as_deref());
+
+    // Stdin is streamed once; spool it when it may be read more than once
+    // (hashing, several accounts, or a retry after a failed upload)
+    let mut spool = Spool::new();
+    if let Some(file) = stdin_file(&args.path, args.name.as_deref())? {
+        if args.skip_uploaded || clients.len() > 1 || args.retries > 0 {
+            files.push(spool.add(&file).await?);
+        } else {
+            files.push(file);
+        }
+    }
+
+    // URLs are streamed; spool those without a Content-Length or that need hashing
+    for url in args.path.iter().filter(|p| remote::is_url(p)) {
+        let result = match remote::probe(url).await {
+            Ok(file) if args.skip_uploaded || file.size().is_none() => spool.add(&file).await,
+            result => result,
+        };
+        match result {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                output::print_failure(&format!("{:#}", e));
+                input_failures.push(InputFailure {
+                    path: url.clone(),
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+
+    // Inputs that failed are still reported below
+    if files.is_empty() && input_failures.is_empty() && !args.watch {
+        bail!("No valid files to upload");
+    }
+
+    // Uploads are always recorded; --skip-uploaded can't go on without the index
+    let index = match UploadIndex::open() {
+        Ok(index) => Some(index),
+        Err(e) if !args.skip_uploaded => {
+            output::print_warning(&format!("Upload index unavailable: {:#}", e));
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut stats = UploadStats::new(Hooks::new(&args));
+    for failure in input_failures {
+        stats.add_record(FileRecord::input_failed(failure));
+    }
+
+    // One limiter per account, kept across watch batches; adaptive mode may double the start value
+    let limiters: Vec<Concurrency> = clients
+        .iter()
+        .map(|_| Concurrency::new(args.concurrent, args.concurrent * 2, args.adaptive))
+        .collect();
+
+    // Start watching before the initial pass so no new file is missed
+    let mut watcher = if args.watch {
+        Some(DirWatcher::new(
+            &args.path,
+            Duration::from_secs(args.settle),
+            args.move_to.as_deref(),
+        )?)
+    } else {
+        None
+    };
+
+    // Chats named by links, resolved once per account for every batch
+    let mut links = Vec::with_capacity(clients.len());
+    for client in &clients {
+        links.push(LinkedChats::resolve(&args, client).await?);
+    }
+
+    // Ctrl+C stops watching, also in the middle of a batch; the run then
+    // finishes as usual (only polled with --watch, so it's installed only then)
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut interrupted = false;
+
+    // A failed initial batch ends the run once its summary and report are out
+    let mut batch_error = None;
+    if !files.is_empty() {
+        let upload = upload_batch(
+            &args,
+            &clients,
+            &limiters,
+            &links,
+            &send,
+            &files,
+            index.as_ref(),
+            encryption.as_ref(),
+            &mut stats,
+        );
+        let result = if watcher.is_some() {
+            tokio::select! {
+                result = upload => result,
+                _ = &mut ctrl_c => {
+                    interrupted = true;
+                    Ok(())
+                }
+            }
+        } else {
+            upload.await
+        };
+        batch_error = result.err();
+    }
+
+    let mut watch_error = None;
+    if let Some(watcher) = watcher.as_mut().filter(|_| batch_error.is_none()) {
+        watcher.mark_seen(&files);
+        if !interrupted {
+            output::print_watching(&args.path);
+        }
+
+        while !interrupted {
+            let mut batch = tokio::select! {
+                batch = watcher.next_batch(&filter) => match batch {
+                    Ok(batch) => batch,
+                    // Watching can't go on; finish the run, then report the error
+                    Err(e) => {
+                        watch_error = Some(e);
+                        break;
+                    }
+                },
+                _ = &mut ctrl_c => break,
+            };
+            sort_files(&mut batch, args.sort, args.sort_expr.as_deref());
+            let upload = upload_batch(
+                &args,
+                &clients,
+                &limiters,
+                &links,
+                &send,
+                &batch,
+                index.as_ref(),
+                encryption.as_ref(),
+                &mut stats,
+            );
+            let result = tokio::select! {
+                result = upload => result,
+                _ = &mut ctrl_c => {
+                    interrupted = true;
+                    Ok(())
+                }
+            };
+            // A failed batch (e.g. a network error while resolving) must not stop watching
+            if let Err(e) = result {
+                output::print_batch_failure(batch.len(), &e);
+            }
+        }
+    }
+
+    output::print_summary(stats.success, stats.failed, stats.skipped);
+    stats.hooks.wait().await;
+    stats
+        .hooks
+        .complete(stats.success, stats.failed, stats.skipped, &stats.records)
+        .await;
+
+    if let Some(ref report) = args.report {
+        write_report(report, &stats.records)?;
+        output::print_report_written(report);
+    }
+
+    match batch_error.or(watch_error) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Print the upload plan without touching the network
+fn dry_run(args: &UploadArgs) -> Result<()> {
+    let filter = FileFilter::new(args)?;
+    let (mut files, _) = match args.archive {
+        Some(format) => collect_archives(args, format, &filter),
+        None => collect_files(&args.path, &filter),
+    };
+    sort_files(&mut files, args.sort, args.sort_expr.as_deref());
+    files.extend(stdin_file(&args.path, args.name.as_deref())?);
+    files.extend(
+        args.path
+            .iter()
+            .filter(|p| remote::is_url(p))
+            .map(|url| remote::url_file(url)),
+    );
 
     if files.is_empty() {
         bail!("No valid files to upload");
     }
 
-    let mut stats = UploadStats::default();
-    stats.add_failed(initial_failed);
+    let plan = build_plan(args, &files)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+    } else {
+        output::print_plan(&plan);
+    }
+
+    Ok(())
+}
+
+/// Chats of `--comment-on` and `--reply-to` links for one account
+#[derive(Default)]
+struct LinkedChats {
+    /// Discussion group and thread of the `--comment-on` post
+    discussion: Option<(ResolvedChat, i32)>,
+    /// Peer ID of the `--reply-to` link's chat
+    reply_chat: Option<i64>,
+}
+
+impl LinkedChats {
+    /// Resolve the links (nothing for accounts that are not logged in)
+    async fn resolve(args: &UploadArgs, client: &TelegramClient) -> Result<Self> {
+        let reply_chat = args
+            .reply_to
+            .as_deref()
+            .and_then(parse_message_link)
+            .map(|(chat, _)| chat);
+        if (args.comment_on.is_none() && reply_chat.is_none()) || !client.is_authorized().await? {
+            return Ok(Self::default());
+        }
+
+        let discussion = match args.comment_on {
+            Some(ref link) => Some(resolve_discussion(client.inner(), link).await?),
+            None => None,
+        };
+        let reply_chat = match reply_chat {
+            Some(chat) => {
+                let chat = resolve_chat(client.inner(), &chat).await?;
+                Some(
+                    chat.peer_id()
+                        .ok_or_else(|| anyhow!("Invalid --reply-to link"))?,
+                )
+            }
+            None => None,
+        };
+        Ok(Self {
+            discussion,
+            reply_chat,
+        })
+    }
+}
+
+/// Upload a set of files with every selected account, then apply `--rm`
+#[allow(clippy::too_many_arguments)]
+async fn upload_batch(
+    args: &UploadArgs,
+    clients: &[Arc<TelegramClient>],
+    limiters: &[Concurrency],
+    links: &[LinkedChats],
+    send: &SendRules,
+    files: &[ValidatedFile],
+    index: Option<&UploadIndex>,
+    encryption: Option<&MasterKey>,
+    stats: &mut UploadStats,
+) -> Result<()> {
+    // Records of this batch start here
+    let first = stats.records.len();
+    // Accounts that uploaded; `--rm-policy all` waits for each of them
+    let mut accounts = 0;
 
     // Upload to each client
-    for client in &clients {
+    for ((client, concurrency), links) in clients.iter().zip(limiters).zip(links) {
         if clients.len() > 1 {
             let account_info = SessionManager::get_account(client.user_id)?;
             let name = account_info
@@ -66,32 +351,57 @@ pub async fn run(
             output::print_account_not_authorized(client.user_id);
             continue;
         }
+        accounts += 1;
 
+        let progress = UploadProgress::new(files);
+        let captions = AlbumCaptions::new(args);
         let ctx = UploadContext {
             client: client.inner(),
-            chat: &chat,
-            topic,
-            caption: &caption,
-            to: &to,
-            concurrent: DEFAULT_CONCURRENT,
+            user_id: client.user_id,
+            chat: &args.chat,
+            send,
+            caption: &args.caption,
+            captions: &captions,
+            to: &args.to,
+            discussion: links.discussion.as_ref().map(|(chat, _)| chat),
+            // Comments reply to the post's copy in the channel's discussion group
+            thread: links.discussion.as_ref().map(|&(_, thread)| thread),
+            reply_chat: links.reply_chat,
+            group_by: &args.group_by,
+            concurrency,
+            part_workers: args.part_workers,
+            index,
+            skip_uploaded: args.skip_uploaded,
+            retry: RetryPolicy {
+                retries: args.retries,
+            },
+            gate: client.flood_gate(),
+            progress: &progress,
+            encryption,
+            resize_photos: args.resize_photos,
         };
 
-        if group {
-            upload_media_groups(&ctx, &files, &mut stats).await?;
+        if args.group {
+            upload_media_groups(&ctx, files, stats).await?;
         } else {
-            upload_single_files(&ctx, &files, &mut stats).await?;
+            upload_single_files(&ctx, files, stats).await?;
         }
     }
 
-    // Remove files after all uploads complete
-    if rm {
-        let removed = remove_files(&files);
+    // Hooks finish before clean-up so the files are still in place
+    stats.hooks.wait().await;
+
+    // Clean up files once enough accounts have confirmed them
+    if args.rm || args.move_to.is_some() {
+        let confirmed = confirmed_files(files, &stats.records[first..], accounts, args.rm_policy);
+        let removed = remove_files(&confirmed, args.move_to.as_deref());
         if removed > 0 {
-            output::print_removed_files(removed);
+            output::print_removed_files(removed, args.move_to.as_deref());
+        }
+        if confirmed.len() < files.len() {
+            output::print_kept_files(files.len() - confirmed.len());
         }
     }
 
-    output::print_summary(stats.success, stats.failed);
-
     Ok(())
 }
@@ -0,0 +1,182 @@
+//! Video checks for `--as auto`
+//!
+//! Short MP4s without an audio track are sent as animations, the way
+//! Telegram sends GIFs. Only the MP4 box headers are read: the movie
+//! duration (`mvhd`) and the handler type of each track (`hdlr`).
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Longest muted MP4 sent as an animation
+const MAX_ANIMATION_SECS: f64 = 60.0;
+
+/// Most boxes read at one level, so a corrupt file can't keep us busy
+const MAX_BOXES: usize = 1024;
+
+/// Tracks and duration of an MP4 file
+#[derive(Debug, Default, PartialEq)]
+struct Mp4Info {
+    duration_secs: f64,
+    has_video: bool,
+    has_audio: bool,
+}
+
+/// Whether a local file is a short MP4 without sound
+pub fn is_animation(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if !matches!(ext.as_str(), "mp4" | "m4v") {
+        return false;
+    }
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    probe(&mut file).is_some_and(|info| {
+        info.has_video && !info.has_audio && info.duration_secs <= MAX_ANIMATION_SECS
+    })
+}
+
+/// Read the duration and track types from the `moov` box
+fn probe<R: Read + Seek>(reader: &mut R) -> Option<Mp4Info> {
+    let len = reader.seek(SeekFrom::End(0)).ok()?;
+    let moov = find_box(reader, 0, len, b"moov")?;
+    let (mvhd, _) = find_box(reader, moov.0, moov.1, b"mvhd")?;
+
+    let mut info = Mp4Info {
+        duration_secs: read_duration(reader, mvhd)?,
+        ..Default::default()
+    };
+    for (kind, start, end) in boxes(reader, moov.0, moov.1)? {
+        if &kind != b"trak" {
+            continue;
+        }
+        let Some(mdia) = find_box(reader, start, end, b"mdia") else {
+            continue;
+        };
+        let Some((hdlr, _)) = find_box(reader, mdia.0, mdia.1, b"hdlr") else {
+            continue;
+        };
+        // Version and flags, pre-defined, then the handler type
+        reader.seek(SeekFrom::Start(hdlr + 8)).ok()?;
+        match &read_array::<4>(reader)? {
+            b"vide" => info.has_video = true,
+            b"soun" => info.has_audio = true,
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+/// Movie duration in seconds from an `mvhd` box starting at `start`
+fn read_duration<R: Read + Seek>(reader: &mut R, start: u64) -> Option<f64> {
+    reader.seek(SeekFrom::Start(start)).ok()?;
+    let version = read_array::<4>(reader)?[0];
+    let (timescale, duration) = if version == 1 {
+        // Creation and modification times are 64-bit in version 1
+        reader.seek(SeekFrom::Current(16)).ok()?;
+        let timescale = u32::from_be_bytes(read_array(reader)?);
+        (timescale, u64::from_be_bytes(read_array(reader)?))
+    } else {
+        reader.seek(SeekFrom::Current(8)).ok()?;
+        let timescale = u32::from_be_bytes(read_array(reader)?);
+        (timescale, u32::from_be_bytes(read_array(reader)?) as u64)
+    };
+    (timescale > 0).then(|| duration as f64 / timescale as f64)
+}
+
+/// Content range of the first box of a type between `start` and `end`
+fn find_box<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+    kind: &[u8; 4],
+) -> Option<(u64, u64)> {
+    boxes(reader, start, end)?
+        .into_iter()
+        .find(|(k, _, _)| k == kind)
+        .map(|(_, start, end)| (start, end))
+}
+
+/// Type and content range of each box between `start` and `end`
+fn boxes<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Option<Vec<([u8; 4], u64, u64)>> {
+    let mut found = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end && found.len() < MAX_BOXES {
+        reader.seek(SeekFrom::Start(pos)).ok()?;
+        let size = u32::from_be_bytes(read_array(reader)?) as u64;
+        let kind = read_array::<4>(reader)?;
+        let (header, size) = match size {
+            // The box runs to the end of its parent
+            0 => (8, end - pos),
+            // A 64-bit size follows the type
+            1 => (16, u64::from_be_bytes(read_array(reader)?)),
+            size => (8, size),
+        };
+        if size < header || pos.checked_add(size)? > end {
+            break;
+        }
+        found.push((kind, pos + header, pos + size));
+        pos += size;
+    }
+    Some(found)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Option<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A box of a type around some content
+    fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(content);
+        data
+    }
+
+    fn track(handler: &[u8; 4]) -> Vec<u8> {
+        let mut hdlr = vec![0u8; 8];
+        hdlr.extend_from_slice(handler);
+        hdlr.extend_from_slice(&[0u8; 12]);
+        mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"hdlr", &hdlr)))
+    }
+
+    fn movie(seconds: u32, handlers: &[&[u8; 4]]) -> Vec<u8> {
+        let mut mvhd = vec![0u8; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&(seconds * 1000).to_be_bytes());
+        let mut moov = mp4_box(b"mvhd", &mvhd);
+        for handler in handlers {
+            moov.extend(track(handler));
+        }
+        let mut data = mp4_box(b"ftyp", b"isom");
+        data.extend(mp4_box(b"moov", &moov));
+        data
+    }
+
+    #[test]
+    fn test_probe() {
+        let info = probe(&mut Cursor::new(movie(5, &[b"vide"]))).unwrap();
+        assert_eq!(
+            info,
+            Mp4Info {
+                duration_secs: 5.0,
+                has_video: true,
+                has_audio: false,
+            }
+        );
+        let info = probe(&mut Cursor::new(movie(5, &[b"vide", b"soun"]))).unwrap();
+        assert!(info.has_audio);
+        assert!(probe(&mut Cursor::new(b"not an mp4".to_vec())).is_none());
+    }
+}
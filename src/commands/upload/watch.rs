@@ -0,0 +1,245 @@
+//! Directory watching for `upload --watch`
+
+use super::file::{collect_under, ValidatedFile};
+use super::filter::FileFilter;
+use anyhow::{bail, Result};
+use notify::event::{CreateKind, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// How often pending files are re-checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A file seen by the watcher that has not settled yet
+struct PendingFile {
+    changed_at: Instant,
+    size: u64,
+}
+
+/// Watches directories and yields files once they stop changing
+pub struct DirWatcher {
+    _watcher: RecommendedWatcher,
+    rx: UnboundedReceiver<notify::Result<Event>>,
+    /// Watched directories
+    roots: Vec<PathBuf>,
+    pending: HashMap<PathBuf, PendingFile>,
+    /// Files already handed out, with their mtime at that point
+    seen: HashMap<PathBuf, SystemTime>,
+    settle: Duration,
+}
+
+impl DirWatcher {
+    /// Start watching all directories in `paths` (recursively)
+    ///
+    /// `move_to` must lie outside the watched directories: files are tracked
+    /// by path, so a moved file would come back as a new one.
+    pub fn new(paths: &[String], settle: Duration, move_to: Option<&Path>) -> Result<Self> {
+        let (tx, rx) = unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })?;
+
+        let mut roots = Vec::new();
+        for path_str in paths {
+            let path = Path::new(path_str);
+            if path.is_dir() {
+                watcher.watch(path, RecursiveMode::Recursive)?;
+                roots.push(path.to_path_buf());
+            }
+        }
+
+        if roots.is_empty() {
+            bail!("--watch requires at least one directory path");
+        }
+        if let Some(dir) = move_to {
+            let target = real_path(dir);
+            if let Some(root) = roots.iter().find(|r| target.starts_with(real_path(r))) {
+                bail!(
+                    "--move-to {} is inside the watched directory {}",
+                    dir.display(),
+                    root.display()
+                );
+            }
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            rx,
+            roots,
+            pending: HashMap::new(),
+            seen: HashMap::new(),
+            settle,
+        })
+    }
+
+    /// Mark files as already handled (e.g. uploaded by the initial pass)
+    pub fn mark_seen(&mut self, files: &[ValidatedFile]) {
+        for file in files {
+            if let Some(mtime) = modified_time(&file.path) {
+                self.seen.insert(file.path.clone(), mtime);
+            }
+        }
+    }
+
+    /// Wait for the next batch of settled files that pass the filter
+    pub async fn next_batch(&mut self, filter: &FileFilter) -> Result<Vec<ValidatedFile>> {
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = self.rx.recv() => {
+                    match event {
+                        Some(Ok(event)) => self.handle_event(event, filter),
+                        Some(Err(e)) => bail!("Watch error: {}", e),
+                        None => bail!("Watcher stopped unexpectedly"),
+                    }
+                }
+                _ = ticker.tick() => {
+                    let ready = self.take_settled();
+                    if !ready.is_empty() {
+                        return Ok(ready);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Track paths touched by a filesystem event
+    fn handle_event(&mut self, event: Event, filter: &FileFilter) {
+        let paths = match event.kind {
+            // Files inside a moved-in directory produce no events of their own
+            EventKind::Create(CreateKind::Folder) => event.paths,
+            EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Any)
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+            | EventKind::Modify(ModifyKind::Name(RenameMode::Any)) => event.paths,
+            // Both: [from, to]
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                event.paths.into_iter().last().into_iter().collect()
+            }
+            _ => return,
+        };
+
+        for path in paths {
+            // Hidden and ignored files are skipped as in the initial pass
+            let Some(root) = self.roots.iter().find(|root| path.starts_with(root)) else {
+                continue;
+            };
+            for file in collect_under(root, &path, filter) {
+                self.touch(file.path);
+            }
+        }
+    }
+
+    /// Reset the settle timer for a path
+    fn touch(&mut self, path: PathBuf) {
+        let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        self.pending.insert(
+            path,
+            PendingFile {
+                changed_at: Instant::now(),
+                size,
+            },
+        );
+    }
+
+    /// Remove and return pending files whose size has been stable for the settle delay
+    fn take_settled(&mut self) -> Vec<ValidatedFile> {
+        let mut ready = Vec::new();
+        let now = Instant::now();
+
+        self.pending.retain(|path, pending| {
+            // Links to files are collected as the file, so follow them here too
+            let Ok(meta) = std::fs::metadata(path) else {
+                return false; // Removed or renamed away
+            };
+            if !meta.is_file() {
+                return false;
+            }
+
+            if meta.len() != pending.size {
+                pending.size = meta.len();
+                pending.changed_at = now;
+                return true;
+            }
+
+            if now.duration_since(pending.changed_at) < self.settle {
+                return true;
+            }
+
+            let mtime = meta.modified().ok();
+            if mtime.is_some() && self.seen.get(path) == mtime.as_ref() {
+                return false; // Already handled and unchanged since
+            }
+            if let Some(mtime) = mtime {
+                self.seen.insert(path.clone(), mtime);
+            }
+            ready.push(ValidatedFile::local(path.clone()));
+            false
+        });
+
+        ready.sort_by(|a, b| a.path.cmp(&b.path));
+        ready
+    }
+}
+
+/// Get a file's modification time
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Absolute path with symlinks resolved; the part that doesn't exist yet is kept as given
+fn real_path(path: &Path) -> PathBuf {
+    if let Ok(real) = path.canonicalize() {
+        return real;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            real_path(parent).join(name)
+        }
+        _ => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_settled_symlink() {
+        let dir = std::env::temp_dir().join(format!("tdlr-watch-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("a.txt");
+        std::fs::write(&target, b"data").unwrap();
+        let link = dir.join("b.txt");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let mut watcher =
+            DirWatcher::new(&[dir.display().to_string()], Duration::ZERO, None).unwrap();
+        watcher.touch(link.clone());
+        let ready = watcher.take_settled();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].path, link);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_to_inside_watched_dir() {
+        let dir = std::env::temp_dir().join(format!("tdlr-watch-move-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = [dir.display().to_string()];
+
+        let inside = dir.join("done/sub");
+        assert!(DirWatcher::new(&paths, Duration::ZERO, Some(&inside)).is_err());
+        let outside = dir.with_extension("done");
+        assert!(DirWatcher::new(&paths, Duration::ZERO, Some(&outside)).is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
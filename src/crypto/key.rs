@@ -0,0 +1,144 @@
+//! Key derivation and the encrypted file header
+
+use aes_gcm::aead::KeyInit;
+use aes_gcm::Aes256Gcm;
+use anyhow::{anyhow, bail, Result};
+use argon2::Argon2;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Magic bytes at the start of every encrypted file (the last byte is the version)
+const MAGIC: &[u8; 8] = b"TDLRENC\x01";
+
+/// Plaintext bytes per encrypted chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Authentication tag appended to every chunk
+pub const TAG_SIZE: usize = 16;
+
+/// Header length: magic, kdf, key salt, file salt, chunk size
+pub const HEADER_SIZE: usize = 8 + 1 + 16 + 16 + 4;
+
+/// What the key is made from
+pub enum Secret {
+    Passphrase(String),
+    KeyFile(Vec<u8>),
+}
+
+impl Secret {
+    /// Read a key file
+    pub fn key_file(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)
+            .map_err(|e| anyhow!("Cannot read key file {}: {}", path.display(), e))?;
+        if data.is_empty() {
+            bail!("Key file {} is empty", path.display());
+        }
+        Ok(Self::KeyFile(data))
+    }
+
+    fn kdf(&self) -> u8 {
+        match self {
+            Self::KeyFile(_) => 0,
+            Self::Passphrase(_) => 1,
+        }
+    }
+}
+
+/// Master key derived from a secret and a salt
+pub struct MasterKey {
+    key: [u8; 32],
+    kdf: u8,
+    salt: [u8; 16],
+}
+
+impl MasterKey {
+    /// Derive a key with a new random salt (for encryption)
+    pub fn generate(secret: &Secret) -> Result<Self> {
+        Self::derive(secret, secret.kdf(), rand::random())
+    }
+
+    /// Derive the key for an existing header (for decryption)
+    fn derive(secret: &Secret, kdf: u8, salt: [u8; 16]) -> Result<Self> {
+        if kdf != secret.kdf() {
+            bail!(match kdf {
+                0 => "File was encrypted with a key file",
+                _ => "File was encrypted with a passphrase",
+            });
+        }
+
+        let mut key = [0u8; 32];
+        match secret {
+            Secret::Passphrase(pass) => Argon2::default()
+                .hash_password_into(pass.as_bytes(), &salt, &mut key)
+                .map_err(|e| anyhow!("Key derivation failed: {}", e))?,
+            Secret::KeyFile(data) => {
+                let mut hasher = Sha256::new();
+                hasher.update(salt);
+                hasher.update(data);
+                key.copy_from_slice(&hasher.finalize());
+            }
+        }
+
+        Ok(Self { key, kdf, salt })
+    }
+
+    /// Start a new file: a fresh per-file key and its header
+    pub fn file_key(&self) -> FileKey {
+        let file_salt: [u8; 16] = rand::random();
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.push(self.kdf);
+        header.extend_from_slice(&self.salt);
+        header.extend_from_slice(&file_salt);
+        header.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+        FileKey::new(&self.key, &file_salt, header)
+    }
+}
+
+/// Per-file key; the header is authenticated with every chunk
+pub struct FileKey {
+    pub cipher: Aes256Gcm,
+    pub header: Vec<u8>,
+}
+
+impl FileKey {
+    fn new(master: &[u8; 32], file_salt: &[u8; 16], header: Vec<u8>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(master);
+        hasher.update(file_salt);
+        let key = hasher.finalize();
+        Self {
+            cipher: Aes256Gcm::new(&key),
+            header,
+        }
+    }
+
+    /// Parse a header and derive the file key from it
+    pub fn from_header(header: &[u8], secret: &Secret) -> Result<Self> {
+        if header.len() < HEADER_SIZE || &header[..8] != MAGIC {
+            bail!("Not a tdlr encrypted file");
+        }
+        let chunk = u32::from_be_bytes(header[41..45].try_into()?);
+        if chunk as usize != CHUNK_SIZE {
+            bail!("Unsupported chunk size {}", chunk);
+        }
+
+        let kdf = header[8];
+        let salt: [u8; 16] = header[9..25].try_into()?;
+        let file_salt: [u8; 16] = header[25..41].try_into()?;
+        let master = MasterKey::derive(secret, kdf, salt)?;
+        Ok(Self::new(
+            &master.key,
+            &file_salt,
+            header[..HEADER_SIZE].to_vec(),
+        ))
+    }
+}
+
+/// Nonce of a chunk: zero prefix, chunk counter, last-chunk flag
+pub fn nonce(counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[7..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
@@ -0,0 +1,49 @@
+//! Client-side encryption of uploaded files
+//!
+//! Files are encrypted with AES-256-GCM in 64 KB chunks. Each chunk's nonce
+//! holds its index and a last-chunk flag, so reordered, dropped or truncated
+//! chunks fail authentication.
+//!
+//! Module structure:
+//! - `key.rs` - Key derivation (Argon2id passphrase or key file) and header
+//! - `stream.rs` - Streaming encryption and decryption
+
+mod key;
+mod stream;
+
+use anyhow::{bail, Result};
+use std::path::Path;
+
+pub use key::{MasterKey, Secret};
+pub use stream::{decrypt, encrypted_size, plain_capacity, EncryptReader};
+
+/// Suffix added to the names of encrypted files
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+/// Environment variable holding the passphrase (there is no command-line
+/// option, which would show up in `ps` and shell history)
+pub const PASSPHRASE_ENV: &str = "TDLR_PASSPHRASE";
+
+/// Get the secret from a key file, `TDLR_PASSPHRASE`, or an interactive prompt
+///
+/// `confirm` asks for the prompted passphrase twice (when encrypting).
+pub fn load_secret(key_file: Option<&Path>, confirm: bool) -> Result<Secret> {
+    if let Some(path) = key_file {
+        return Secret::key_file(path);
+    }
+    if let Some(pass) = std::env::var_os(PASSPHRASE_ENV) {
+        let Some(pass) = pass.to_str().filter(|p| !p.is_empty()) else {
+            bail!("{} must be a non-empty UTF-8 string", PASSPHRASE_ENV);
+        };
+        return Ok(Secret::Passphrase(pass.to_string()));
+    }
+
+    let pass = rpassword::prompt_password("Passphrase: ")?;
+    if pass.is_empty() {
+        bail!("Passphrase must not be empty");
+    }
+    if confirm && rpassword::prompt_password("Confirm passphrase: ")? != pass {
+        bail!("Passphrases do not match");
+    }
+    Ok(Secret::Passphrase(pass))
+}
@@ -0,0 +1,212 @@
+//! Chunked AES-256-GCM encryption and decryption of streams
+
+use super::key::{nonce, FileKey, Secret, CHUNK_SIZE, HEADER_SIZE, TAG_SIZE};
+use aes_gcm::aead::{Aead, Payload};
+use anyhow::{anyhow, bail, Result};
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Size of the encrypted output for a plaintext size
+pub fn encrypted_size(plain: u64) -> u64 {
+    let chunks = plain.div_ceil(CHUNK_SIZE as u64).max(1);
+    HEADER_SIZE as u64 + plain + chunks * TAG_SIZE as u64
+}
+
+/// Largest plaintext size whose encrypted output fits in `limit` bytes
+pub fn plain_capacity(limit: u64) -> u64 {
+    let chunk = (CHUNK_SIZE + TAG_SIZE) as u64;
+    let body = limit.saturating_sub(HEADER_SIZE as u64);
+    let full = body / chunk;
+    let rest = (body % chunk).saturating_sub(TAG_SIZE as u64);
+    full * CHUNK_SIZE as u64 + rest
+}
+
+/// AsyncRead that encrypts the wrapped reader
+pub struct EncryptReader<R> {
+    inner: R,
+    key: FileKey,
+    counter: u32,
+    /// Plaintext read ahead (one byte more than a chunk tells it isn't the last)
+    pending: Vec<u8>,
+    eof: bool,
+    /// Encrypted output not yet handed out
+    out: Vec<u8>,
+    out_pos: usize,
+    done: bool,
+}
+
+impl<R> EncryptReader<R> {
+    pub fn new(inner: R, key: FileKey) -> Self {
+        let out = key.header.clone();
+        Self {
+            inner,
+            key,
+            counter: 0,
+            pending: Vec::with_capacity(CHUNK_SIZE + 1),
+            eof: false,
+            out,
+            out_pos: 0,
+            done: false,
+        }
+    }
+
+    /// Encrypt the next chunk from `pending` into `out`
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let len = self.pending.len().min(CHUNK_SIZE);
+        let payload = Payload {
+            msg: &self.pending[..len],
+            aad: &self.key.header,
+        };
+        let sealed = self
+            .key
+            .cipher
+            .encrypt(&nonce(self.counter, last).into(), payload)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.pending.drain(..len);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("file too large to encrypt"))?;
+        self.out = sealed;
+        self.out_pos = 0;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for EncryptReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.out_pos < this.out.len() {
+                let n = buf.remaining().min(this.out.len() - this.out_pos);
+                buf.put_slice(&this.out[this.out_pos..this.out_pos + n]);
+                this.out_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.done {
+                return Poll::Ready(Ok(()));
+            }
+
+            // Fill up to one byte past a chunk, or until EOF
+            while !this.eof && this.pending.len() <= CHUNK_SIZE {
+                let mut scratch = [0u8; 16 * 1024];
+                let want = (CHUNK_SIZE + 1 - this.pending.len()).min(scratch.len());
+                let mut read = ReadBuf::new(&mut scratch[..want]);
+                match Pin::new(&mut this.inner).poll_read(cx, &mut read) {
+                    Poll::Ready(Ok(())) if read.filled().is_empty() => this.eof = true,
+                    Poll::Ready(Ok(())) => this.pending.extend_from_slice(read.filled()),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            let last = this.eof && this.pending.len() <= CHUNK_SIZE;
+            this.seal(last)?;
+        }
+    }
+}
+
+/// Decrypt a whole stream written by `EncryptReader`
+pub fn decrypt(mut input: impl Read, mut output: impl Write, secret: &Secret) -> Result<()> {
+    let mut header = [0u8; HEADER_SIZE];
+    input
+        .read_exact(&mut header)
+        .map_err(|_| anyhow!("Not a tdlr encrypted file"))?;
+    let key = FileKey::from_header(&header, secret)?;
+
+    let sealed_size = CHUNK_SIZE + TAG_SIZE;
+    let mut current = read_chunk(&mut input, sealed_size)?;
+    let mut counter: u32 = 0;
+    loop {
+        // A chunk is the last one when nothing follows it
+        let next = if current.len() == sealed_size {
+            read_chunk(&mut input, sealed_size)?
+        } else {
+            Vec::new()
+        };
+        let last = next.is_empty();
+
+        let payload = Payload {
+            msg: &current,
+            aad: &key.header,
+        };
+        let plain = key
+            .cipher
+            .decrypt(&nonce(counter, last).into(), payload)
+            .map_err(|_| anyhow!("Wrong key, or the file is corrupted or truncated"))?;
+        output.write_all(&plain)?;
+
+        if last {
+            break;
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| anyhow!("Too many chunks"))?;
+        current = next;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Read up to `size` bytes (fewer only at EOF)
+fn read_chunk(input: &mut impl Read, size: usize) -> Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    input.by_ref().take(size as u64).read_to_end(&mut chunk)?;
+    if !chunk.is_empty() && chunk.len() < TAG_SIZE {
+        bail!("File is truncated");
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::key::MasterKey;
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn roundtrip(len: usize) {
+        let secret = Secret::KeyFile(b"test key".to_vec());
+        let plain: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let key = MasterKey::generate(&secret).unwrap().file_key();
+
+        let mut sealed = Vec::new();
+        EncryptReader::new(plain.as_slice(), key)
+            .read_to_end(&mut sealed)
+            .await
+            .unwrap();
+        assert_eq!(sealed.len() as u64, encrypted_size(len as u64));
+
+        let mut out = Vec::new();
+        decrypt(sealed.as_slice(), &mut out, &secret).unwrap();
+        assert_eq!(out, plain);
+
+        // Dropping the last chunk must be detected
+        if len > CHUNK_SIZE {
+            let cut = HEADER_SIZE + CHUNK_SIZE + TAG_SIZE;
+            assert!(decrypt(&sealed[..cut], &mut Vec::new(), &secret).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_sizes() {
+        for len in [0, 10, CHUNK_SIZE, CHUNK_SIZE * 2 + 7] {
+            roundtrip(len).await;
+        }
+    }
+
+    #[test]
+    fn test_plain_capacity_fits() {
+        let limit = 10 * 1024 * 1024;
+        let plain = plain_capacity(limit);
+        assert!(encrypted_size(plain) <= limit);
+        assert!(encrypted_size(plain + 1) > limit);
+    }
+}
@@ -4,6 +4,7 @@
 
 pub mod cli;
 pub mod commands;
+pub mod crypto;
 pub mod telegram;
 pub mod utils;
 
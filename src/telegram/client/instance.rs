@@ -1,6 +1,7 @@
 //! Single Telegram client instance
 
 use crate::telegram::session::SessionManager;
+use crate::telegram::upload::FloodGate;
 use anyhow::Result;
 use grammers_client::Client;
 use grammers_mtsender::{ConnectionParams, SenderPool};
@@ -27,6 +28,7 @@ pub struct TelegramClient {
     pub user_id: i64,
     session: Arc<SqliteSession>,
     network_handle: JoinHandle<()>,
+    flood_gate: FloodGate,
 }
 
 impl TelegramClient {
@@ -52,6 +54,7 @@ impl TelegramClient {
             user_id,
             session,
             network_handle,
+            flood_gate: FloodGate::default(),
         })
     }
 
@@ -77,6 +80,7 @@ impl TelegramClient {
             user_id: 0, // Will be set after login
             session,
             network_handle,
+            flood_gate: FloodGate::default(),
         })
     }
 
@@ -85,6 +89,11 @@ impl TelegramClient {
         &self.client
     }
 
+    /// Get the account-wide flood wait gate
+    pub fn flood_gate(&self) -> &FloodGate {
+        &self.flood_gate
+    }
+
     /// Check if authorized
     pub async fn is_authorized(&self) -> Result<bool> {
         Ok(self.client.is_authorized().await?)
@@ -6,10 +6,49 @@ use grammers_client::Client;
 use grammers_tl_types as tl;
 
 /// Resolved chat information
+#[derive(Clone)]
 pub struct ResolvedChat {
     pub input_peer: tl::enums::InputPeer,
     pub name: String,
-    pub peer: Option<Peer>,
+    pub username: Option<String>,
+}
+
+impl ResolvedChat {
+    /// Bare peer ID of the chat (`None` for Saved Messages)
+    pub fn peer_id(&self) -> Option<i64> {
+        match &self.input_peer {
+            tl::enums::InputPeer::User(u) => Some(u.user_id),
+            tl::enums::InputPeer::Chat(c) => Some(c.chat_id),
+            tl::enums::InputPeer::Channel(ch) => Some(ch.channel_id),
+            tl::enums::InputPeer::UserFromMessage(u) => Some(u.user_id),
+            tl::enums::InputPeer::ChannelFromMessage(ch) => Some(ch.channel_id),
+            tl::enums::InputPeer::PeerSelf | tl::enums::InputPeer::Empty => None,
+        }
+    }
+
+    /// Chat ID in Bot API form (-100 prefix for channels, negative for groups)
+    pub fn marked_id(&self) -> Option<i64> {
+        match &self.input_peer {
+            tl::enums::InputPeer::Chat(c) => Some(-c.chat_id),
+            tl::enums::InputPeer::Channel(_) | tl::enums::InputPeer::ChannelFromMessage(_) => {
+                self.peer_id().map(|id| -1_000_000_000_000 - id)
+            }
+            _ => self.peer_id(),
+        }
+    }
+
+    /// Public link to a message (channels and supergroups only)
+    pub fn message_link(&self, msg_id: i32) -> Option<String> {
+        match &self.input_peer {
+            tl::enums::InputPeer::Channel(_) | tl::enums::InputPeer::ChannelFromMessage(_) => {
+                match self.username {
+                    Some(ref username) => Some(format!("https://t.me/{}/{}", username, msg_id)),
+                    None => Some(format!("https://t.me/c/{}/{}", self.peer_id()?, msg_id)),
+                }
+            }
+            _ => None,
+        }
+    }
 }
 
 /// Resolve chat from string (username, ID, or special values)
@@ -21,7 +60,7 @@ pub async fn resolve_chat(client: &Client, chat_str: &str) -> Result<ResolvedCha
         return Ok(ResolvedChat {
             input_peer: tl::types::InputPeerSelf {}.into(),
             name: "Saved Messages".to_string(),
-            peer: None,
+            username: None,
         });
     }
 
@@ -40,6 +79,85 @@ pub async fn resolve_chat(client: &Client, chat_str: &str) -> Result<ResolvedCha
     resolve_username(client, chat_str).await
 }
 
+/// Parse a message link into a chat for `resolve_chat` and a message ID
+///
+/// Accepts `t.me/<username>/<id>` and `t.me/c/<chat>/<id>`, optionally with a
+/// topic before the message ID.
+pub fn parse_message_link(link: &str) -> Option<(String, i32)> {
+    let link = link.trim();
+    let link = link
+        .strip_prefix("https://")
+        .or_else(|| link.strip_prefix("http://"))
+        .unwrap_or(link);
+    let path = link
+        .strip_prefix("t.me/")
+        .or_else(|| link.strip_prefix("telegram.me/"))?;
+    let path = path.split(['?', '#']).next()?;
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+
+    let (chat, rest) = match parts.as_slice() {
+        ["c", id, rest @ ..] => (format!("-100{}", id.parse::<i64>().ok()?), rest),
+        [username, rest @ ..] => (format!("@{}", username), rest),
+        [] => return None,
+    };
+    // <message> or <topic>/<message>
+    match rest {
+        [msg_id] | [_, msg_id] => Some((chat, msg_id.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Resolve the comment thread of a channel post link
+///
+/// Returns the channel's discussion group and the ID of the post's copy
+/// there, which comments reply to.
+pub async fn resolve_discussion(client: &Client, link: &str) -> Result<(ResolvedChat, i32)> {
+    let Some((chat, msg_id)) = parse_message_link(link) else {
+        bail!("Invalid message link '{}'", link);
+    };
+    let channel = resolve_chat(client, &chat).await?;
+
+    let tl::enums::messages::DiscussionMessage::Message(discussion) = client
+        .invoke(&tl::functions::messages::GetDiscussionMessage {
+            peer: channel.input_peer,
+            msg_id,
+        })
+        .await?;
+
+    // Albums come back as several messages; the thread starts at the first
+    let Some(thread) = discussion
+        .messages
+        .iter()
+        .filter_map(|m| match m {
+            tl::enums::Message::Message(m) => Some(m),
+            _ => None,
+        })
+        .min_by_key(|m| m.id)
+    else {
+        bail!("Post {} has no comment thread", link);
+    };
+    let tl::enums::Peer::Channel(ref group) = thread.peer_id else {
+        bail!("Post {} has no comment thread", link);
+    };
+
+    let chat = discussion.chats.iter().find_map(|c| match c {
+        tl::enums::Chat::Channel(ch) if ch.id == group.channel_id => Some(ResolvedChat {
+            input_peer: tl::types::InputPeerChannel {
+                channel_id: ch.id,
+                access_hash: ch.access_hash.unwrap_or(0),
+            }
+            .into(),
+            name: ch.title.clone(),
+            username: ch.username.clone(),
+        }),
+        _ => None,
+    });
+    match chat {
+        Some(chat) => Ok((chat, thread.id)),
+        None => bail!("Discussion group of {} not found", link),
+    }
+}
+
 /// Resolve chat by username using high-level API
 async fn resolve_username(client: &Client, username: &str) -> Result<ResolvedChat> {
     let peer = client
@@ -48,12 +166,13 @@ async fn resolve_username(client: &Client, username: &str) -> Result<ResolvedCha
         .ok_or_else(|| anyhow::anyhow!("Username @{} not found", username))?;
 
     let name = peer.name().unwrap_or("Unknown").to_string();
+    let username = peer.username().map(str::to_string);
     let input_peer = peer_to_input_peer(&peer);
 
     Ok(ResolvedChat {
         input_peer,
         name,
-        peer: Some(peer),
+        username,
     })
 }
 
@@ -79,12 +198,13 @@ async fn resolve_by_id(client: &Client, id: i64) -> Result<ResolvedChat> {
         // Match against the normalized target_id
         if peer_id == target_id || peer_id == id || peer_id == id.abs() {
             let name = peer.name().unwrap_or("Unknown").to_string();
+            let username = peer.username().map(str::to_string);
             let input_peer = peer_to_input_peer(peer);
 
             return Ok(ResolvedChat {
                 input_peer,
                 name,
-                peer: Some(peer.clone()),
+                username,
             });
         }
     }
@@ -133,3 +253,20 @@ fn peer_to_input_peer(peer: &Peer) -> tl::enums::InputPeer {
         .into(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message_link() {
+        let parse = |link| parse_message_link(link);
+        assert_eq!(parse("https://t.me/news/42"), Some(("@news".into(), 42)));
+        assert_eq!(
+            parse("t.me/c/1234567890/7/42?single"),
+            Some(("-1001234567890".into(), 42))
+        );
+        assert_eq!(parse("https://t.me/news"), None);
+        assert_eq!(parse("https://example.com/news/42"), None);
+    }
+}
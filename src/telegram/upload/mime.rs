@@ -1,28 +1,103 @@
 //! MIME type and media utilities
 
+use anyhow::{bail, Result};
 use std::path::Path;
+use std::str::FromStr;
 
-/// Check if file extension is supported for media group
-pub fn is_media_group_supported(path: &Path) -> bool {
-    let ext = path
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+/// How a file is sent (`--as`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MediaKind {
+    /// Chosen from the file extension
+    #[default]
+    Auto,
+    Document,
+    Photo,
+    Video,
+    Audio,
+    Voice,
+    Animation,
+    VideoNote,
+}
 
-    matches!(
-        ext.as_str(),
-        "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp" | // photos
-        "mp4" | "mkv" | "avi" | "mov" | "webm" | "m4v" | "3gp" // videos
-    )
+impl MediaKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Document => "document",
+            Self::Photo => "photo",
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::Voice => "voice",
+            Self::Animation => "animation",
+            Self::VideoNote => "video-note",
+        }
+    }
+
+    /// Concrete kind for a file name (`Auto` picks photo, video, animation
+    /// for GIFs, or document; short muted MP4s are spotted before this, see
+    /// `commands/upload/video.rs`)
+    pub fn resolve(self, name: &str) -> Self {
+        if self != Self::Auto {
+            return self;
+        }
+        let ext = Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        if ext == "gif" {
+            Self::Animation
+        } else if is_photo_ext(&ext) {
+            Self::Photo
+        } else if is_video_ext(&ext) {
+            Self::Video
+        } else {
+            Self::Document
+        }
+    }
+
+    /// Album type of a concrete kind: photos mix with videos, documents and
+    /// audio form their own albums, the rest can't be grouped
+    pub fn album(self) -> Option<&'static str> {
+        match self {
+            Self::Photo | Self::Video => Some("media"),
+            Self::Document => Some("document"),
+            Self::Audio => Some("audio"),
+            Self::Auto | Self::Voice | Self::Animation | Self::VideoNote => None,
+        }
+    }
+}
+
+impl FromStr for MediaKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "auto" => Self::Auto,
+            "document" => Self::Document,
+            "photo" => Self::Photo,
+            "video" => Self::Video,
+            "audio" => Self::Audio,
+            "voice" => Self::Voice,
+            "animation" => Self::Animation,
+            "video-note" | "video_note" => Self::VideoNote,
+            _ => bail!(
+                "Unknown media kind '{}' (auto, document, photo, video, audio, voice, animation, video-note)",
+                s
+            ),
+        })
+    }
 }
 
 /// Check if extension is a photo
+///
+/// HEIC counts only with the `heic` feature, which converts it to JPEG.
 pub fn is_photo_ext(ext: &str) -> bool {
+    let ext = ext.to_lowercase();
     matches!(
-        ext.to_lowercase().as_str(),
+        ext.as_str(),
         "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp"
-    )
+    ) || (cfg!(feature = "heic") && matches!(ext.as_str(), "heic" | "heif"))
 }
 
 /// Check if extension is a video
@@ -3,15 +3,29 @@
 //! Module structure:
 //! - `chat.rs` - Chat resolution (username, ID)
 //! - `single.rs` - Single file upload
-//! - `group.rs` - Media group upload
+//! - `group.rs` - Media group (album) items
 //! - `mime.rs` - MIME type utilities
+//! - `progress.rs` - Upload progress tracking
+//! - `retry.rs` - Retry policy for flood waits and transient errors
+//! - `send.rs` - Sending media with silent, spoiler, protect and schedule options
+//! - `stream.rs` - Chunked upload of files and streams of unknown size
 
 mod chat;
 mod group;
 mod mime;
+mod progress;
+mod retry;
+mod send;
 mod single;
+mod stream;
 
-pub use chat::{resolve_chat, ResolvedChat};
-pub use group::{upload_media_group, MAX_MEDIA_GROUP_SIZE};
-pub use mime::is_media_group_supported;
-pub use single::upload_file;
+pub use chat::{parse_message_link, resolve_chat, resolve_discussion, ResolvedChat};
+pub use group::{prepare_album_item, AlbumMedia, MAX_MEDIA_GROUP_SIZE};
+pub use mime::MediaKind;
+pub use progress::Progress;
+pub use retry::{
+    classify, is_photo_rejected, with_retry, Failure, FloodGate, RetryPolicy, DEFAULT_RETRIES,
+};
+pub use send::{photo_as_document, send_album, send_media, SendOptions};
+pub use single::upload_media;
+pub use stream::{UploadSource, DEFAULT_PART_WORKERS};
@@ -0,0 +1,80 @@
+//! Upload progress tracking
+
+use indicatif::ProgressBar;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Progress bars updated while a file's bytes are uploaded
+#[derive(Clone)]
+pub struct Progress {
+    /// Per-file bar
+    pub file: ProgressBar,
+    /// Overall bar shared by all files of a run
+    pub overall: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// Progress that is not displayed
+    pub fn hidden() -> Self {
+        Self {
+            file: ProgressBar::hidden(),
+            overall: None,
+        }
+    }
+
+    /// Advance by the given number of bytes
+    ///
+    /// Files of unknown size grow the overall total as they are read.
+    pub fn inc(&self, bytes: u64) {
+        self.file.inc(bytes);
+        if let Some(ref overall) = self.overall {
+            if self.file.length().is_none() {
+                overall.inc_length(bytes);
+            }
+            overall.inc(bytes);
+        }
+    }
+
+    /// Start the file over (e.g. on retry), taking its bytes back from the overall bar
+    pub fn restart(&self) {
+        if let Some(ref overall) = self.overall {
+            overall.dec(self.file.position());
+            if self.file.length().is_none() {
+                overall.dec_length(self.file.position());
+            }
+        }
+        self.file.set_position(0);
+    }
+}
+
+/// Progress-tracking wrapper for AsyncRead
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, progress: &Progress) -> Self {
+        Self {
+            inner,
+            progress: progress.clone(),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &result {
+            let read = (buf.filled().len() - before) as u64;
+            self.progress.inc(read);
+        }
+        result
+    }
+}
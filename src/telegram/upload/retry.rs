@@ -0,0 +1,225 @@
+//! Retry policy for flood waits and transient errors
+
+use grammers_mtsender::InvocationError;
+use std::future::Future;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default number of retries per operation
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Base delay for exponential backoff on transient errors
+const BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// Maximum delay between transient retries
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Retry settings for upload operations
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff delay before the given retry (1-based)
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        BASE_DELAY.saturating_mul(factor).min(MAX_DELAY)
+    }
+}
+
+/// Account-wide pause, shared by every upload task of one account
+///
+/// A FLOOD_WAIT applies to the whole account, so one task hitting it
+/// holds back all others until the wait is over.
+#[derive(Default)]
+pub struct FloodGate {
+    until: Mutex<Option<Instant>>,
+}
+
+impl FloodGate {
+    /// Pause the account for the given duration (extends an existing pause)
+    pub fn pause(&self, duration: Duration) {
+        let target = Instant::now() + duration;
+        let mut until = self.until.lock().unwrap();
+        if until.is_none_or(|u| u < target) {
+            *until = Some(target);
+        }
+    }
+
+    /// Wait until the account is no longer paused
+    pub async fn wait(&self) {
+        loop {
+            let until = *self.until.lock().unwrap();
+            match until {
+                Some(t) if t > Instant::now() => tokio::time::sleep_until(t.into()).await,
+                _ => return,
+            }
+        }
+    }
+}
+
+/// How a failed operation should be handled
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    /// Server asked to wait before retrying
+    FloodWait(Duration),
+    /// Network hiccup or server-side error; retry with backoff
+    Transient,
+    /// Telegram lost the uploaded parts (`FILE_PART_MISSING`); only a new upload helps
+    Reupload,
+    /// Retrying won't help
+    Fatal,
+}
+
+/// Classify an error returned by an upload operation
+pub fn classify(err: &anyhow::Error) -> Failure {
+    let Some(err) = find_invocation_error(err) else {
+        return Failure::Fatal;
+    };
+
+    match err {
+        InvocationError::Rpc(rpc) => match rpc.name.as_str() {
+            "FLOOD_WAIT" | "FLOOD_PREMIUM_WAIT" | "SLOWMODE_WAIT" => {
+                Failure::FloodWait(Duration::from_secs(rpc.value.unwrap_or(1) as u64 + 1))
+            }
+            "FILE_PART_MISSING" => Failure::Reupload,
+            "RPC_CALL_FAIL" | "TIMEOUT" | "INTERDC_CALL_ERROR" | "INTERDC_CALL_RICH_ERROR" => {
+                Failure::Transient
+            }
+            _ if rpc.code >= 500 || rpc.code == -503 => Failure::Transient,
+            _ => Failure::Fatal,
+        },
+        InvocationError::Io(_) | InvocationError::Transport(_) | InvocationError::Dropped => {
+            Failure::Transient
+        }
+        _ => Failure::Fatal,
+    }
+}
+
+/// Whether Telegram rejected a photo (`PHOTO_INVALID_DIMENSIONS`, `PHOTO_SAVE_FILE_INVALID`, ...)
+///
+/// The uploaded file can still be sent as a document.
+pub fn is_photo_rejected(err: &anyhow::Error) -> bool {
+    matches!(
+        find_invocation_error(err),
+        Some(InvocationError::Rpc(rpc)) if rpc.name.starts_with("PHOTO_")
+    )
+}
+
+/// Find the Telegram invocation error behind an error chain
+///
+/// `upload_stream` wraps invocation errors in `io::Error`.
+fn find_invocation_error(err: &anyhow::Error) -> Option<&InvocationError> {
+    err.chain().find_map(|cause| {
+        cause.downcast_ref::<InvocationError>().or_else(|| {
+            cause
+                .downcast_ref::<io::Error>()
+                .and_then(|e| e.get_ref())
+                .and_then(|e| e.downcast_ref::<InvocationError>())
+        })
+    })
+}
+
+/// Run an operation, retrying flood waits and transient errors
+///
+/// `on_retry` is called with the error and the delay before each retry.
+/// `Failure::Reupload` errors are returned at once: repeating a send doesn't
+/// bring lost parts back, so the caller has to upload again.
+pub async fn with_retry<T, F, Fut, R>(
+    policy: &RetryPolicy,
+    gate: &FloodGate,
+    mut op: F,
+    on_retry: R,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+    R: Fn(&anyhow::Error, Duration),
+{
+    let mut attempt = 0;
+    loop {
+        gate.wait().await;
+
+        let err = match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+
+        attempt += 1;
+        if attempt > policy.retries {
+            return Err(err);
+        }
+
+        match classify(&err) {
+            Failure::FloodWait(wait) => {
+                on_retry(&err, wait);
+                gate.pause(wait);
+            }
+            Failure::Transient => {
+                let delay = policy.backoff(attempt);
+                on_retry(&err, delay);
+                tokio::time::sleep(delay).await;
+            }
+            Failure::Reupload | Failure::Fatal => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grammers_mtsender::RpcError;
+
+    fn rpc(code: i32, name: &str, value: Option<u32>) -> anyhow::Error {
+        InvocationError::Rpc(RpcError {
+            code,
+            name: name.to_string(),
+            value,
+            caused_by: None,
+        })
+        .into()
+    }
+
+    #[test]
+    fn test_classify_flood_wait() {
+        let err = rpc(420, "FLOOD_WAIT", Some(31));
+        assert_eq!(classify(&err), Failure::FloodWait(Duration::from_secs(32)));
+    }
+
+    #[test]
+    fn test_classify_wrapped_in_io() {
+        let inner = InvocationError::Rpc(RpcError {
+            code: 400,
+            name: "FILE_PART_MISSING".to_string(),
+            value: Some(3),
+            caused_by: None,
+        });
+        let err: anyhow::Error = io::Error::other(inner).into();
+        assert_eq!(classify(&err), Failure::Reupload);
+    }
+
+    #[test]
+    fn test_classify_fatal() {
+        assert_eq!(classify(&rpc(400, "PEER_ID_INVALID", None)), Failure::Fatal);
+        let err: anyhow::Error = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        assert_eq!(classify(&err), Failure::Fatal);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy { retries: 10 };
+        assert_eq!(policy.backoff(1), Duration::from_secs(2));
+        assert_eq!(policy.backoff(3), Duration::from_secs(8));
+        assert_eq!(policy.backoff(10), MAX_DELAY);
+    }
+}
@@ -0,0 +1,318 @@
+//! Sending uploaded files with per-message options
+//!
+//! The high-level `InputMessage` / `send_album` API has no way to set the
+//! spoiler, protected content or schedule flags, so messages are sent with
+//! raw `messages.sendMedia` / `messages.sendMultiMedia` requests.
+
+use super::chat::ResolvedChat;
+use super::group::MAX_MEDIA_GROUP_SIZE;
+use super::mime::MediaKind;
+use anyhow::{anyhow, bail, Result};
+use grammers_client::parsers::parse_html_message;
+use grammers_client::types::media::{Media, Uploaded};
+use grammers_client::Client;
+use grammers_tl_types as tl;
+use std::collections::HashMap;
+
+/// Options applied to a sent message or album
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SendOptions {
+    /// Send without a notification
+    pub silent: bool,
+    /// Hide the media behind a spoiler
+    pub spoiler: bool,
+    /// Protected content: no forwarding or saving
+    pub protect: bool,
+    /// Unix time to schedule the message for
+    pub schedule: Option<i32>,
+    /// Message (or forum topic) to reply to
+    pub reply_to: Option<i32>,
+    /// Photo, video, document, ... (`Auto` picks by extension)
+    pub kind: MediaKind,
+}
+
+/// Input media for an uploaded file, sent as the given kind
+pub fn uploaded_media(
+    uploaded: Uploaded,
+    name: &str,
+    kind: MediaKind,
+    spoiler: bool,
+) -> tl::enums::InputMedia {
+    let kind = kind.resolve(name);
+    if kind == MediaKind::Photo {
+        return tl::types::InputMediaUploadedPhoto {
+            spoiler,
+            file: uploaded.raw,
+            stickers: None,
+            ttl_seconds: None,
+        }
+        .into();
+    }
+
+    let mut attributes = vec![tl::types::DocumentAttributeFilename {
+        file_name: name.to_string(),
+    }
+    .into()];
+    let video = |round_message| {
+        tl::types::DocumentAttributeVideo {
+            round_message,
+            supports_streaming: true,
+            nosound: false,
+            duration: 0.0,
+            w: 0,
+            h: 0,
+            preload_prefix_size: None,
+            video_start_ts: None,
+            video_codec: None,
+        }
+        .into()
+    };
+    let audio = |voice| {
+        tl::types::DocumentAttributeAudio {
+            voice,
+            duration: 0,
+            title: None,
+            performer: None,
+            waveform: None,
+        }
+        .into()
+    };
+    match kind {
+        MediaKind::Video => attributes.push(video(false)),
+        MediaKind::VideoNote => attributes.push(video(true)),
+        MediaKind::Audio => attributes.push(audio(false)),
+        MediaKind::Voice => attributes.push(audio(true)),
+        MediaKind::Animation => attributes.push(tl::types::DocumentAttributeAnimated {}.into()),
+        _ => {}
+    }
+
+    tl::types::InputMediaUploadedDocument {
+        nosound_video: kind == MediaKind::Animation,
+        force_file: kind == MediaKind::Document,
+        spoiler,
+        file: uploaded.raw,
+        thumb: None,
+        mime_type: mime_guess::from_path(name)
+            .first_or_octet_stream()
+            .essence_str()
+            .to_string(),
+        attributes,
+        stickers: None,
+        ttl_seconds: None,
+        video_cover: None,
+        video_timestamp: None,
+    }
+    .into()
+}
+
+/// The same uploaded photo as a document, for when Telegram rejects the photo
+pub fn photo_as_document(
+    media: &tl::enums::InputMedia,
+    name: &str,
+) -> Option<tl::enums::InputMedia> {
+    match media {
+        tl::enums::InputMedia::UploadedPhoto(photo) => Some(uploaded_media(
+            Uploaded::from_raw(photo.file.clone()),
+            name,
+            MediaKind::Document,
+            photo.spoiler,
+        )),
+        _ => None,
+    }
+}
+
+/// Upload media to the chat without sending it, for use in an album
+pub async fn prepare_album_media(
+    client: &Client,
+    chat: &ResolvedChat,
+    media: tl::enums::InputMedia,
+    spoiler: bool,
+) -> Result<tl::enums::InputMedia> {
+    let uploaded = client
+        .invoke(&tl::functions::messages::UploadMedia {
+            business_connection_id: None,
+            peer: chat.input_peer.clone(),
+            media,
+        })
+        .await?;
+
+    let mut media = Media::from_raw(uploaded)
+        .and_then(|m| m.to_raw_input_media())
+        .ok_or_else(|| anyhow!("Telegram returned unsupported media"))?;
+    // The spoiler flag is not carried over by the conversion
+    match media {
+        tl::enums::InputMedia::Photo(ref mut photo) => photo.spoiler = spoiler,
+        tl::enums::InputMedia::Document(ref mut doc) => doc.spoiler = spoiler,
+        _ => {}
+    }
+    Ok(media)
+}
+
+/// Send one media message, returning its ID
+///
+/// `random_id` must stay the same when a send is retried, so Telegram drops
+/// a repeat of a message that was already posted.
+pub async fn send_media(
+    client: &Client,
+    chat: &ResolvedChat,
+    media: tl::enums::InputMedia,
+    caption: Option<&str>,
+    options: &SendOptions,
+    random_id: i64,
+) -> Result<i32> {
+    let (message, entities) = parse_caption(caption);
+
+    let updates = client
+        .invoke(&tl::functions::messages::SendMedia {
+            silent: options.silent,
+            background: false,
+            clear_draft: false,
+            noforwards: options.protect,
+            update_stickersets_order: false,
+            invert_media: false,
+            allow_paid_floodskip: false,
+            peer: chat.input_peer.clone(),
+            reply_to: input_reply_to(options.reply_to),
+            media,
+            message,
+            random_id,
+            reply_markup: None,
+            entities,
+            schedule_date: options.schedule,
+            send_as: None,
+            quick_reply_shortcut: None,
+            effect: None,
+            allow_paid_stars: None,
+            suggested_post: None,
+        })
+        .await?;
+
+    sent_ids(updates, &[random_id])
+        .pop()
+        .flatten()
+        .ok_or_else(|| anyhow!("Telegram did not return the sent message"))
+}
+
+/// Send prepared media as one album with a caption and random ID per item
+///
+/// Returns the message ID of each item, if known. As with `send_media`, the
+/// random IDs must stay the same across retries.
+pub async fn send_album(
+    client: &Client,
+    chat: &ResolvedChat,
+    media: Vec<tl::enums::InputMedia>,
+    captions: &[Option<String>],
+    options: &SendOptions,
+    random_ids: &[i64],
+) -> Result<Vec<Option<i32>>> {
+    if media.is_empty() || media.len() > MAX_MEDIA_GROUP_SIZE {
+        bail!("Media group must have 1 to {} files", MAX_MEDIA_GROUP_SIZE);
+    }
+    if captions.len() != media.len() || random_ids.len() != media.len() {
+        bail!("Expected one caption and random ID per file");
+    }
+
+    let multi_media = media
+        .into_iter()
+        .zip(random_ids)
+        .zip(captions)
+        .map(|((media, &random_id), caption)| {
+            let (message, entities) = parse_caption(caption.as_deref());
+            tl::types::InputSingleMedia {
+                media,
+                random_id,
+                message,
+                entities,
+            }
+            .into()
+        })
+        .collect();
+
+    let updates = client
+        .invoke(&tl::functions::messages::SendMultiMedia {
+            silent: options.silent,
+            background: false,
+            clear_draft: false,
+            noforwards: options.protect,
+            update_stickersets_order: false,
+            invert_media: false,
+            allow_paid_floodskip: false,
+            peer: chat.input_peer.clone(),
+            reply_to: input_reply_to(options.reply_to),
+            multi_media,
+            schedule_date: options.schedule,
+            send_as: None,
+            quick_reply_shortcut: None,
+            effect: None,
+            allow_paid_stars: None,
+        })
+        .await?;
+
+    Ok(sent_ids(updates, random_ids))
+}
+
+/// Parse an HTML caption into text and entities
+fn parse_caption(caption: Option<&str>) -> (String, Option<Vec<tl::enums::MessageEntity>>) {
+    let Some(caption) = caption else {
+        return (String::new(), None);
+    };
+    let (text, entities) = parse_html_message(caption);
+    // User mentions need an access hash to be sent; keep them as plain text
+    let entities: Vec<_> = entities
+        .into_iter()
+        .filter(|e| !matches!(e, tl::enums::MessageEntity::MentionName(_)))
+        .collect();
+    (text, (!entities.is_empty()).then_some(entities))
+}
+
+fn input_reply_to(msg_id: Option<i32>) -> Option<tl::enums::InputReplyTo> {
+    msg_id.map(|reply_to_msg_id| {
+        tl::types::InputReplyToMessage {
+            reply_to_msg_id,
+            top_msg_id: None,
+            reply_to_peer_id: None,
+            quote_text: None,
+            quote_entities: None,
+            quote_offset: None,
+            monoforum_peer_id: None,
+            todo_item_id: None,
+        }
+        .into()
+    })
+}
+
+/// Message IDs for the given random IDs from a send response
+fn sent_ids(updates: tl::enums::Updates, random_ids: &[i64]) -> Vec<Option<i32>> {
+    let updates = match updates {
+        tl::enums::Updates::UpdateShortSentMessage(sent) => {
+            return random_ids.iter().map(|_| Some(sent.id)).collect();
+        }
+        tl::enums::Updates::Updates(u) => u.updates,
+        tl::enums::Updates::Combined(u) => u.updates,
+        _ => Vec::new(),
+    };
+
+    let mut by_random_id = HashMap::new();
+    let mut new_ids = Vec::new();
+    for update in updates {
+        match update {
+            tl::enums::Update::MessageId(u) => {
+                by_random_id.insert(u.random_id, u.id);
+            }
+            tl::enums::Update::NewMessage(u) => new_ids.push(u.message.id()),
+            tl::enums::Update::NewChannelMessage(u) => new_ids.push(u.message.id()),
+            tl::enums::Update::NewScheduledMessage(u) => new_ids.push(u.message.id()),
+            _ => {}
+        }
+    }
+
+    random_ids
+        .iter()
+        .map(|rnd| {
+            by_random_id.get(rnd).copied().or_else(|| {
+                // Without a random ID mapping, a single new message must be ours
+                (random_ids.len() == 1 && new_ids.len() == 1).then(|| new_ids[0])
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,269 @@
+//! Chunked upload with configurable part concurrency
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use grammers_client::types::media::Uploaded;
+use grammers_client::Client;
+use grammers_tl_types as tl;
+use std::io::{self, Cursor};
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::Mutex;
+
+/// Size of each uploaded part (Telegram maximum)
+pub const PART_SIZE: usize = 512 * 1024;
+
+/// Files above this size must use the big file API
+const BIG_FILE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Default number of parts uploaded in parallel for one file
+pub const DEFAULT_PART_WORKERS: usize = 4;
+
+/// Data to upload, with its size if known in advance
+pub struct UploadSource {
+    pub reader: Box<dyn AsyncRead + Unpin + Send>,
+    pub size: Option<u64>,
+    /// File name sent to Telegram (also decides the send mode)
+    pub name: String,
+}
+
+impl UploadSource {
+    /// Open a local file
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file")
+            .to_string();
+
+        Ok(Self {
+            reader: Box::new(file),
+            size: Some(size),
+            name,
+        })
+    }
+}
+
+/// One part read from the stream
+struct Part {
+    index: i32,
+    bytes: Vec<u8>,
+    last: bool,
+}
+
+/// Sequential part reader shared by upload workers
+///
+/// With an unknown total, the reader looks one part ahead to tell which
+/// part is the last.
+struct PartReader<'a, S> {
+    stream: &'a mut S,
+    next_part: i32,
+    total_parts: Option<i32>,
+    ahead: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<'a, S: AsyncRead + Unpin> PartReader<'a, S> {
+    fn new(stream: &'a mut S, total_parts: Option<i32>) -> Self {
+        Self {
+            stream,
+            next_part: 0,
+            total_parts,
+            ahead: None,
+            done: false,
+        }
+    }
+
+    /// Fill one part from the stream (shorter only at EOF)
+    async fn read_part(&mut self) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0; PART_SIZE];
+        let mut read = 0;
+        while read < buffer.len() {
+            let n = self.stream.read(&mut buffer[read..]).await?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        buffer.truncate(read);
+        Ok(buffer)
+    }
+
+    /// Read the next part, or `None` after the last one
+    async fn next(&mut self) -> io::Result<Option<Part>> {
+        let (bytes, last) = match self.total_parts {
+            Some(total) => {
+                if self.next_part >= total {
+                    return Ok(None);
+                }
+                let bytes = self.read_part().await?;
+                let last = self.next_part == total - 1;
+                if bytes.len() < PART_SIZE && !last {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "reached EOF before reaching the last file part",
+                    ));
+                }
+                (bytes, last)
+            }
+            None => {
+                if self.done {
+                    return Ok(None);
+                }
+                let bytes = match self.ahead.take() {
+                    Some(bytes) => bytes,
+                    None => self.read_part().await?,
+                };
+                let next = if bytes.len() < PART_SIZE {
+                    Vec::new()
+                } else {
+                    self.read_part().await?
+                };
+                let last = next.is_empty();
+                if last {
+                    self.done = true;
+                } else {
+                    self.ahead = Some(next);
+                }
+                (bytes, last)
+            }
+        };
+
+        let index = self.next_part;
+        self.next_part += 1;
+        Ok(Some(Part { index, bytes, last }))
+    }
+}
+
+/// Upload a stream, with `workers` parts in flight for big files
+///
+/// Without a known size, up to `BIG_FILE_SIZE` bytes are buffered first:
+/// shorter streams are sent as small files, longer ones are streamed as a
+/// big file whose part count is only reported with the last part.
+pub async fn upload_stream<S: AsyncRead + Unpin>(
+    client: &Client,
+    stream: &mut S,
+    size: Option<usize>,
+    name: String,
+    workers: usize,
+) -> io::Result<Uploaded> {
+    let file_id: i64 = rand::random();
+    let name = if name.is_empty() {
+        "a".to_string()
+    } else {
+        name
+    };
+
+    let mut prefix = Vec::new();
+    let size = match size {
+        Some(size) => Some(size),
+        None => {
+            (&mut *stream)
+                .take(BIG_FILE_SIZE as u64 + 1)
+                .read_to_end(&mut prefix)
+                .await?;
+            (prefix.len() <= BIG_FILE_SIZE).then_some(prefix.len())
+        }
+    };
+    let mut stream = Cursor::new(prefix).chain(stream);
+    let total_parts = size.map(|size| size.div_ceil(PART_SIZE).max(1) as i32);
+    let reader = Mutex::new(PartReader::new(&mut stream, total_parts));
+
+    if let Some(size) = size.filter(|&size| size <= BIG_FILE_SIZE) {
+        let mut md5 = md5::Context::new();
+        let mut reader = reader.into_inner();
+        while let Some(part) = reader.next().await? {
+            md5.consume(&part.bytes);
+            let ok = client
+                .invoke(&tl::functions::upload::SaveFilePart {
+                    file_id,
+                    file_part: part.index,
+                    bytes: part.bytes,
+                })
+                .await
+                .map_err(io::Error::other)?;
+            check_saved(ok)?;
+        }
+
+        return Ok(Uploaded::from_raw(
+            tl::types::InputFile {
+                id: file_id,
+                parts: size.div_ceil(PART_SIZE).max(1) as i32,
+                name,
+                md5_checksum: format!("{:x}", md5.finalize()),
+            }
+            .into(),
+        ));
+    }
+
+    let mut tasks = FuturesUnordered::new();
+    for _ in 0..workers.max(1) {
+        let reader = &reader;
+        tasks.push(async move {
+            loop {
+                let next = reader.lock().await.next().await?;
+                let Some(part) = next else {
+                    return Ok::<_, io::Error>(());
+                };
+                let file_total_parts = match total_parts {
+                    Some(total) => total,
+                    None if part.last => part.index + 1,
+                    None => -1,
+                };
+                let ok = client
+                    .invoke(&tl::functions::upload::SaveBigFilePart {
+                        file_id,
+                        file_part: part.index,
+                        file_total_parts,
+                        bytes: part.bytes,
+                    })
+                    .await
+                    .map_err(io::Error::other)?;
+                check_saved(ok)?;
+            }
+        });
+    }
+
+    while let Some(res) = tasks.next().await {
+        res?;
+    }
+    drop(tasks);
+
+    Ok(Uploaded::from_raw(
+        tl::types::InputFileBig {
+            id: file_id,
+            parts: reader.into_inner().next_part,
+            name,
+        }
+        .into(),
+    ))
+}
+
+/// Turn a `false` save result into an error
+fn check_saved(ok: bool) -> io::Result<()> {
+    if ok {
+        Ok(())
+    } else {
+        Err(io::Error::other("server failed to store uploaded data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unknown_size_marks_last_part() {
+        let mut data = Cursor::new(vec![7u8; PART_SIZE * 2]);
+        let mut reader = PartReader::new(&mut data, None);
+
+        let first = reader.next().await.unwrap().unwrap();
+        assert_eq!((first.index, first.last), (0, false));
+        let second = reader.next().await.unwrap().unwrap();
+        assert_eq!((second.index, second.last), (1, true));
+        assert_eq!(second.bytes.len(), PART_SIZE);
+        assert!(reader.next().await.unwrap().is_none());
+    }
+}
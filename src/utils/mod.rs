@@ -12,3 +12,16 @@ pub fn format_size(size: u64) -> String {
         format!("{:.2} GB", size as f64 / 1024.0 / 1024.0 / 1024.0)
     }
 }
+
+/// Format a duration as e.g. `1h 30m`, `5m 10s` or `42s`
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h {}m", h, m)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}